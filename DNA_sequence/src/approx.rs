@@ -0,0 +1,473 @@
+// Approximate matching: occurrences of a pattern with up to k differences, to find real sites despite sequencing errors
+// Budget::Mismatches(k) allows k substituted bases (Hamming distance), the occurrence has the length of the pattern
+// Budget::Edits(k) allows k substitutions, insertions or deletions (Levenshtein distance)
+//
+// A pattern with ranges ("A1-1,C2-4") is tried in every shape it can take (ACC, ACCC, ACCCC) and each occurrence
+// reports the smallest distance among them; IUPAC codes work as in the exact search
+// The search goes start by start like the exact one: with mismatches each shape is compared base by base,
+// with edits Myers' bit-parallel algorithm, anchored at the start, gives the distance of the occurrence
+// ending at each of the following bases; the best end is the one with the smallest distance (the longest
+// among equals, as the greedy exact search prefers)
+// Occurrences come in order of start: with MatchMode::NonOverlapping the search goes on from the end of
+// the previous one, with MatchMode::Overlapping from the base after its start
+// A byte that is not ASCII (an accented letter pasted into the sequence) is never part of an occurrence:
+// the search restarts after it, as if the sequence were split there
+
+use std::cmp::Reverse;
+use std::iter::Peekable;
+
+use crate::pattern::{accepts, Ambiguity, MatchMode, Pattern, PatternError};
+
+// Limite alle forme che può prendere un pattern nella ricerca approssimata
+const MAX_SHAPES: usize = 256;
+
+const WORD: usize = 64;
+
+// Quante differenze sono ammesse, e di che tipo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Budget {
+    Mismatches(usize),  // solo sostituzioni
+    Edits(usize),       // sostituzioni, inserzioni e cancellazioni
+}
+
+// Pattern compilato per la ricerca approssimata
+#[derive(Debug, Clone)]
+pub struct Approx {
+    shapes: Vec<Shape>,
+    budget: Budget,
+}
+
+// Una forma del pattern: le basi ammesse in ogni posizione e, per Myers, per ogni carattere
+// della sequenza le posizioni che lo ammettono (una maschera di bit ogni 64 posizioni)
+#[derive(Debug, Clone)]
+struct Shape {
+    masks: Vec<u8>,
+    ambiguity: Ambiguity,
+    words: usize,
+    peq: Vec<u64>,      // peq[c * words + w]
+}
+
+// Stato di Myers per una forma letta da un inizio fisso: differenze verticali positive e negative
+// tra righe vicine, e distanza nell'ultima riga
+#[derive(Debug, Clone)]
+struct Myers {
+    pv: Vec<u64>,
+    mv: Vec<u64>,
+    score: usize,
+}
+
+// Iteratore sulle occorrenze approssimate, come DNAIter: (posizione, slice trovata, distanza), in ordine di posizione
+pub struct ApproxIter<'a> {
+    s: &'a str,
+    approx: Option<Approx>,
+    mode: MatchMode,
+    pos: usize,     // prossimo inizio da provare
+}
+
+// Più pattern, ognuno con il suo budget, con le occorrenze di tutti in ordine di posizione
+// Non è un automa come Automaton (multi.rs), che cerca solo occorrenze esatte: ogni pattern ha la sua
+// scansione della sequenza e le occorrenze vengono unite, quindi il costo è O(pattern × lunghezza)
+pub struct MultiApprox<'p> {
+    patterns: Vec<(&'p str, Approx)>,
+}
+
+impl Approx {
+    pub fn new(pattern: &Pattern, budget: Budget) -> Result<Approx, PatternError> {
+        let shapes: Vec<Shape> = pattern
+            .shapes(MAX_SHAPES)?
+            .into_iter()
+            .filter(|masks| !masks.is_empty())
+            .map(|masks| Shape::new(masks, pattern.ambiguity()))
+            .collect();
+
+        // con tante modifiche quante basi, anche la stringa vuota sarebbe un'occorrenza
+        if let Budget::Edits(k) = budget {
+            if shapes.iter().any(|shape| shape.masks.len() <= k) {
+                return Err(PatternError::TooManyEdits(k));
+            }
+        }
+
+        Ok(Approx { shapes, budget })
+    }
+}
+
+impl Shape {
+    fn new(masks: Vec<u8>, ambiguity: Ambiguity) -> Shape {
+        let words = masks.len().div_ceil(WORD);
+        let mut peq = vec![0; 256 * words];
+        for c in 0..256 {
+            for (i, &bases) in masks.iter().enumerate() {
+                if accepts(bases, c as u8, ambiguity) {
+                    peq[c * words + i / WORD] |= 1 << (i % WORD);
+                }
+            }
+        }
+        Shape { masks, ambiguity, words, peq }
+    }
+
+    fn len(&self) -> usize {
+        self.masks.len()
+    }
+
+    // Sostituzioni per far corrispondere la forma da `start`, None se sono più di k, la sequenza finisce prima
+    // o c'è un byte non ASCII
+    fn mismatches(&self, seq: &[u8], start: usize, k: usize) -> Option<usize> {
+        let window = seq.get(start..start + self.len())?;
+        let mut count = 0;
+        for (&bases, &b) in self.masks.iter().zip(window) {
+            if !b.is_ascii() {
+                return None;
+            }
+            if !accepts(bases, b, self.ambiguity) {
+                count += 1;
+                if count > k {
+                    return None;
+                }
+            }
+        }
+        Some(count)
+    }
+
+    // Modifiche per far corrispondere la forma da `start`: la fine migliore con la sua distanza, None se
+    // nessuna fine è nel budget; un byte non ASCII chiude la finestra
+    fn best_end(&self, seq: &[u8], start: usize, k: usize) -> Option<(usize, usize)> {
+        let mut myers = Myers::new(self);
+        let mut best = None;
+
+        for (i, &c) in seq[start..].iter().take(self.len() + k).enumerate() {
+            if !c.is_ascii() {
+                break;
+            }
+            let distance = myers.step(self, c);
+            // a parità di distanza vince la fine più lontana
+            if distance <= k && best.is_none_or(|(best, _)| distance <= best) {
+                best = Some((distance, start + i + 1));
+            }
+        }
+        best
+    }
+}
+
+impl Myers {
+    fn new(shape: &Shape) -> Myers {
+        Myers { pv: vec![!0; shape.words], mv: vec![0; shape.words], score: shape.len() }
+    }
+
+    // Legge una base della sequenza, blocco per blocco: ogni blocco passa al successivo la differenza
+    // orizzontale della sua ultima riga (come in Hyyrö, "A bit-vector algorithm for computing
+    // Levenshtein and Damerau edit distances"); nell'ultimo blocco conta la riga dell'ultima posizione
+    // L'occorrenza inizia dalla prima base letta, quindi la riga sopra il pattern cresce di uno a ogni base
+    fn step(&mut self, shape: &Shape, c: u8) -> usize {
+        let eqs = &shape.peq[c as usize * shape.words..][..shape.words];
+        let mut hin: i64 = 1;
+
+        for (w, &eqw) in eqs.iter().enumerate() {
+            let (pv, mv) = (self.pv[w], self.mv[w]);
+            let negative = (hin < 0) as u64;
+            let xv = eqw | mv;
+            let eq = eqw | negative;
+            let xh = ((eq & pv).wrapping_add(pv) ^ pv) | eq;
+            let ph = mv | !(xh | pv);
+            let mh = pv & xh;
+
+            let last = if w == shape.words - 1 { (shape.len() - 1) % WORD } else { WORD - 1 };
+            let hout = ((ph >> last) & 1) as i64 - ((mh >> last) & 1) as i64;
+
+            let ph = (ph << 1) | (hin > 0) as u64;
+            let mh = (mh << 1) | negative;
+            self.pv[w] = mh | !(xv | ph);
+            self.mv[w] = ph & xv;
+            hin = hout;
+        }
+
+        self.score = (self.score as i64 + hin) as usize;
+        self.score
+    }
+}
+
+impl<'a> ApproxIter<'a> {
+    // Il pattern non valido (o con un budget troppo grande) non trova niente, come in DNAIter
+    pub fn new(s: &'a str, seq: &str, budget: Budget, mode: MatchMode) -> ApproxIter<'a> {
+        let approx = Pattern::parse(seq).ok().and_then(|pattern| Approx::new(&pattern, budget).ok());
+        ApproxIter::with_approx(s, approx, mode)
+    }
+
+    fn with_approx(s: &'a str, approx: Option<Approx>, mode: MatchMode) -> ApproxIter<'a> {
+        ApproxIter { s, approx, mode, pos: 0 }
+    }
+
+    // Con le sostituzioni: il primo inizio in cui una forma corrisponde, con la distanza più piccola
+    fn next_mismatches(&mut self, k: usize) -> Option<(usize, &'a str, usize)> {
+        let seq = self.s.as_bytes();
+        let shapes = &self.approx.as_ref()?.shapes;
+
+        while self.pos < seq.len() {
+            let start = self.pos;
+            let best = shapes
+                .iter()
+                .filter_map(|shape| shape.mismatches(seq, start, k).map(|distance| (distance, shape.len())))
+                .min_by_key(|&(distance, _)| distance);
+
+            if let Some((distance, len)) = best {
+                self.pos = self.mode.resume(start, start + len);
+                return Some((start, &self.s[start..start + len], distance));
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    // Con le modifiche: il primo inizio da cui una forma corrisponde, con la distanza più piccola
+    // e a parità di distanza la fine più lontana
+    fn next_edits(&mut self, k: usize) -> Option<(usize, &'a str, usize)> {
+        let seq = self.s.as_bytes();
+        let shapes = &self.approx.as_ref()?.shapes;
+
+        while self.pos < seq.len() {
+            let start = self.pos;
+            let best = shapes
+                .iter()
+                .filter_map(|shape| shape.best_end(seq, start, k))
+                .min_by_key(|&(distance, end)| (distance, Reverse(end)));
+
+            if let Some((distance, end)) = best {
+                self.pos = self.mode.resume(start, end);
+                return Some((start, &self.s[start..end], distance));
+            }
+            self.pos += 1;
+        }
+        None
+    }
+}
+
+impl<'a> Iterator for ApproxIter<'a> {
+    type Item = (usize, &'a str, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.approx.as_ref()?.budget {
+            Budget::Mismatches(k) => self.next_mismatches(k),
+            Budget::Edits(k) => self.next_edits(k),
+        }
+    }
+}
+
+impl<'p> MultiApprox<'p> {
+    // Compila i pattern, o restituisce l'indice del primo non valido con l'errore
+    pub fn new(patterns: &[(&'p str, Budget)]) -> Result<MultiApprox<'p>, (usize, PatternError)> {
+        let mut compiled = Vec::new();
+        for (i, &(p, budget)) in patterns.iter().enumerate() {
+            let pattern = Pattern::parse(p).map_err(|e| (i, e))?;
+            compiled.push((p, Approx::new(&pattern, budget).map_err(|e| (i, e))?));
+        }
+        Ok(MultiApprox { patterns: compiled })
+    }
+
+    // Occorrenze di tutti i pattern: (posizione, slice trovata, pattern, distanza), ordinate per posizione e,
+    // a parità di posizione, per l'ordine in cui i pattern sono stati dati a new
+    // Ogni ApproxIter dà le sue in ordine di posizione, quindi basta prendere ogni volta la prima tra le loro prossime
+    pub fn iter<'a>(&self, s: &'a str, mode: MatchMode) -> impl Iterator<Item = (usize, &'a str, &'p str, usize)> + 'a
+    where
+        'p: 'a,
+    {
+        let mut iters: Vec<(&'p str, Peekable<ApproxIter<'a>>)> = self
+            .patterns
+            .iter()
+            .map(|(p, approx)| (*p, ApproxIter::with_approx(s, Some(approx.clone()), mode).peekable()))
+            .collect();
+
+        std::iter::from_fn(move || {
+            let (_, i) = iters
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, (_, iter))| iter.peek().map(|&(start, _, _)| (start, i)))
+                .min()?;
+            let (p, iter) = &mut iters[i];
+            iter.next().map(|(start, found, distance)| (start, found, *p, distance))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hits<'a>(s: &'a str, seq: &str, budget: Budget) -> Vec<(usize, &'a str, usize)> {
+        ApproxIter::new(s, seq, budget, MatchMode::Overlapping).collect()
+    }
+
+    #[test]
+    fn non_ascii_bytes_are_not_part_of_an_occurrence() {
+        assert_eq!(hits("AéA", "AA", Budget::Mismatches(1)), vec![]);
+        assert_eq!(hits("AéA", "AAA", Budget::Edits(1)), vec![]);
+        assert_eq!(hits("AAéAC", "AC", Budget::Mismatches(1)), vec![(0, "AA", 1), (4, "AC", 0)]);
+        assert_eq!(hits("ACéAC", "AC", Budget::Edits(1)), vec![(0, "AC", 0), (1, "C", 1), (4, "AC", 0), (5, "C", 1)]);
+        assert_eq!(hits("CéGT", "CGT", Budget::Edits(1)), vec![(3, "GT", 1)]);
+    }
+
+    // Distanze di Levenshtein tra la forma e ogni prefisso di text, con la tabella intera:
+    // row[j] = distanza tra la forma e le prime j basi
+    fn levenshtein(text: &[u8], masks: &[u8]) -> Vec<usize> {
+        let mut row: Vec<usize> = (0..=text.len()).collect();
+        for (i, &bases) in masks.iter().enumerate() {
+            let mut next = vec![i + 1; text.len() + 1];
+            for (j, &b) in text.iter().enumerate() {
+                let substitution = row[j] + !accepts(bases, b, Ambiguity::Exact) as usize;
+                next[j + 1] = substitution.min(row[j + 1] + 1).min(next[j] + 1);
+            }
+            row = next;
+        }
+        row
+    }
+
+    // Ricerca ingenua: per ogni inizio tutte le fini e tutte le forme, la distanza più piccola
+    // e a parità di distanza la fine più lontana (più lunga della forma più lunga con k basi in più
+    // un'occorrenza non può essere, avrebbe più di k inserzioni)
+    fn naive_find<'a>(s: &'a str, pattern: &str, budget: Budget, mode: MatchMode) -> Vec<(usize, &'a str, usize)> {
+        let shapes = Pattern::parse(pattern).unwrap().shapes(MAX_SHAPES).unwrap();
+        let k = match budget {
+            Budget::Mismatches(k) | Budget::Edits(k) => k,
+        };
+        let reach = shapes.iter().map(Vec::len).max().unwrap_or(0) + k;
+        let seq = s.as_bytes();
+        let mut found = Vec::new();
+        let mut pos = 0;
+
+        while pos < seq.len() {
+            let start = pos;
+            let window = &seq[start..seq.len().min(start + reach)];
+            let best = shapes
+                .iter()
+                .flat_map(|masks| {
+                    let distances = match budget {
+                        Budget::Mismatches(_) => (0..=window.len())
+                            .map(|j| {
+                                if j == masks.len() {
+                                    masks.iter().zip(window).filter(|&(&bases, &b)| !accepts(bases, b, Ambiguity::Exact)).count()
+                                } else {
+                                    usize::MAX
+                                }
+                            })
+                            .collect(),
+                        Budget::Edits(_) => levenshtein(window, masks),
+                    };
+                    distances.into_iter().enumerate().skip(1).map(move |(j, distance)| (distance, start + j))
+                })
+                .filter(|&(distance, _)| distance <= k)
+                .min_by_key(|&(distance, end)| (distance, Reverse(end)));
+
+            match best {
+                Some((distance, end)) => {
+                    found.push((start, &s[start..end], distance));
+                    pos = mode.resume(start, end);
+                }
+                None => pos += 1,
+            }
+        }
+        found
+    }
+
+    fn random_bases(random: &mut impl FnMut(usize) -> usize, n: usize) -> String {
+        (0..n).map(|_| b"ACGT"[random(4)] as char).collect()
+    }
+
+    fn xorshift(mut state: u64) -> impl FnMut(usize) -> usize {
+        move |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn edits_find_every_occurrence() {
+        let edits = |s, mode| ApproxIter::new(s, "ATA", Budget::Edits(1), mode).collect::<Vec<_>>();
+        assert_eq!(edits("ATAATA", MatchMode::NonOverlapping), vec![(0, "ATA", 0), (3, "ATA", 0)]);
+        assert!(edits("ATATA", MatchMode::Overlapping).contains(&(2, "ATA", 0)));
+
+        let mut random = xorshift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let s = random_bases(&mut random, 60);
+            let runs: Vec<String> = (0..1 + random(3))
+                .map(|_| {
+                    let min = 1 + random(2);
+                    format!("{}{}-{}", b"ACGTRN"[random(6)] as char, min, min + random(3))
+                })
+                .collect();
+            let pattern = runs.join(",");
+
+            for budget in [Budget::Mismatches(1), Budget::Edits(1), Budget::Edits(2)] {
+                if Approx::new(&Pattern::parse(&pattern).unwrap(), budget).is_err() {
+                    continue;
+                }
+                for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+                    let found: Vec<_> = ApproxIter::new(&s, &pattern, budget, mode).collect();
+                    assert_eq!(found, naive_find(&s, &pattern, budget, mode), "{} {:?} {:?} in {}", pattern, budget, mode, s);
+                }
+            }
+        }
+    }
+
+    // Un pattern di più di 64 basi usa più blocchi di Myers
+    #[test]
+    fn edits_with_a_pattern_longer_than_a_word() {
+        let mut random = xorshift(0x9e37_79b9_7f4a_7c15);
+        for len in [64, 65, 100, 150] {
+            let pattern = random_bases(&mut random, len);
+            // una copia con due sostituzioni, un'inserzione e una cancellazione, tra basi casuali
+            let mut copy = pattern.clone().into_bytes();
+            copy[10] = if copy[10] == b'A' { b'C' } else { b'A' };
+            copy[len - 5] = if copy[len - 5] == b'G' { b'T' } else { b'G' };
+            copy.insert(len / 2, b'T');
+            copy.remove(len / 3);
+            let s = format!("{}{}{}", random_bases(&mut random, 40), String::from_utf8(copy).unwrap(), random_bases(&mut random, 40));
+
+            for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+                let found: Vec<_> = ApproxIter::new(&s, &pattern, Budget::Edits(4), mode).collect();
+                assert!(found.iter().any(|&(start, _, _)| (38..=42).contains(&start)), "{} {:?}", len, found);
+                assert_eq!(found, naive_find(&s, &pattern, Budget::Edits(4), mode), "{}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn occurrences_are_in_order_of_position() {
+        let mut random = xorshift(0x9e37_79b9_7f4a_7c15);
+
+        for _ in 0..30 {
+            let s = random_bases(&mut random, 120);
+            // elementi con conteggi diversi, così le forme hanno lunghezze diverse
+            let mut pattern = |runs: usize| -> String {
+                let runs: Vec<String> = (0..runs)
+                    .map(|_| {
+                        let min = 1 + random(2);
+                        format!("{}{}-{}", b"ACGT"[random(4)] as char, min, min + random(4))
+                    })
+                    .collect();
+                runs.join(",")
+            };
+            let patterns = [pattern(3), pattern(1), pattern(4)];
+            let budgets = [(patterns[0].as_str(), Budget::Edits(2)), (patterns[1].as_str(), Budget::Mismatches(1)), (patterns[2].as_str(), Budget::Edits(3))];
+            let multi = match MultiApprox::new(&budgets) {
+                Ok(multi) => multi,
+                Err(_) => continue,     // un pattern troppo corto per il budget
+            };
+
+            for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+                let found: Vec<(usize, &str, &str, usize)> = multi.iter(&s, mode).collect();
+                let keys: Vec<(usize, usize)> = found
+                    .iter()
+                    .map(|&(start, _, pattern, _)| (start, budgets.iter().position(|&(p, _)| p == pattern).unwrap()))
+                    .collect();
+                assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]), "{:?} {:?}", budgets, keys);
+
+                // le occorrenze di ogni pattern sono proprio quelle della sua ricerca da solo
+                for &(p, budget) in budgets.iter() {
+                    let own: Vec<(usize, &str, usize)> =
+                        found.iter().filter(|&&(_, _, q, _)| q == p).map(|&(start, sub, _, distance)| (start, sub, distance)).collect();
+                    assert_eq!(own, ApproxIter::new(&s, p, budget, mode).collect::<Vec<_>>());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// Streaming readers for FASTA and FASTQ files, one record at a time
+// The format is chosen by the first line: '>' for FASTA, '@' for FASTQ
+//
+//   >id description            @id description
+//   ACGTACGT...                ACGTACGT...
+//   acgtNNNN...                +
+//                              IIIIHHHH...
+//
+// Sequences may span many lines (in FASTQ too, the quality then spans as many bases) and are kept as they are,
+// lowercase soft-masked bases included: the searches treat them as the same bases in uppercase
+// Only the record being read is in memory, so files of any size can be scanned
+
+use std::fmt;
+use std::io::{self, BufRead};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub id: String,
+    pub description: String,
+    pub sequence: String,
+    pub qualities: Option<String>,  // solo per FASTQ, un carattere per base
+}
+
+#[derive(Debug)]
+pub enum FastxError {
+    Io(io::Error),
+    Format(usize, &'static str),    // riga (da 1) e cosa non va
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Fasta,
+    Fastq,
+}
+
+// Iteratore sui record di un file, che viene letto man mano
+pub struct Records<R: BufRead> {
+    reader: R,
+    format: Option<Format>,     // None finché non si è letta la prima riga
+    header: Option<String>,     // intestazione del prossimo record FASTA, già letta
+    line: usize,                // righe lette finora
+}
+
+impl<R: BufRead> Records<R> {
+    pub fn new(reader: R) -> Records<R> {
+        Records { reader, format: None, header: None, line: 0 }
+    }
+
+    // Prossima riga senza il fine riga, None a fine file
+    fn read_line(&mut self) -> Result<Option<String>, FastxError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).map_err(FastxError::Io)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Some(line))
+    }
+
+    // Record FASTA: l'intestazione è già stata letta, le righe della sequenza arrivano fino alla prossima '>'
+    fn fasta(&mut self, header: String) -> Result<Record, FastxError> {
+        let (id, description) = split_header(&header[1..]);
+        let mut sequence = String::new();
+
+        while let Some(line) = self.read_line()? {
+            if line.starts_with('>') {
+                self.header = Some(line);
+                break;
+            }
+            sequence.push_str(line.trim());
+        }
+
+        Ok(Record { id, description, sequence, qualities: None })
+    }
+
+    // Record FASTQ: sequenza fino alla riga '+', poi qualità finché non ne ha una per base
+    // (le righe di qualità possono iniziare con '@', quindi conta solo la lunghezza)
+    fn fastq(&mut self, header: String) -> Result<Record, FastxError> {
+        let (id, description) = split_header(&header[1..]);
+        let mut sequence = String::new();
+
+        loop {
+            match self.read_line()? {
+                Some(line) if line.starts_with('+') => break,
+                Some(line) => sequence.push_str(line.trim()),
+                None => return Err(FastxError::Format(self.line, "missing '+' line")),
+            }
+        }
+
+        let mut qualities = String::new();
+        while qualities.len() < sequence.len() {
+            match self.read_line()? {
+                Some(line) => qualities.push_str(line.trim()),
+                None => return Err(FastxError::Format(self.line, "fewer qualities than bases")),
+            }
+        }
+        if qualities.len() != sequence.len() {
+            return Err(FastxError::Format(self.line, "more qualities than bases"));
+        }
+
+        Ok(Record { id, description, sequence, qualities: Some(qualities) })
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = Result<Record, FastxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // l'intestazione del prossimo record, saltando le righe vuote
+        let header = match self.header.take() {
+            Some(header) => header,
+            None => loop {
+                match self.read_line() {
+                    Ok(Some(line)) if line.trim().is_empty() => continue,
+                    Ok(Some(line)) => break line,
+                    Ok(None) => return None,
+                    Err(e) => return Some(Err(e)),
+                }
+            },
+        };
+
+        let format = match (self.format, header.chars().next()) {
+            (None | Some(Format::Fasta), Some('>')) => Format::Fasta,
+            (None | Some(Format::Fastq), Some('@')) => Format::Fastq,
+            _ => return Some(Err(FastxError::Format(self.line, "expected a record header"))),
+        };
+        self.format = Some(format);
+
+        Some(match format {
+            Format::Fasta => self.fasta(header),
+            Format::Fastq => self.fastq(header),
+        })
+    }
+}
+
+// "id resto della riga" -> (id, descrizione)
+fn split_header(header: &str) -> (String, String) {
+    match header.split_once(char::is_whitespace) {
+        Some((id, description)) => (id.to_string(), description.trim().to_string()),
+        None => (header.to_string(), String::new()),
+    }
+}
+
+impl fmt::Display for FastxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FastxError::Io(e) => write!(f, "{}", e),
+            FastxError::Format(line, message) => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Vec<Result<Record, FastxError>> {
+        Records::new(input.as_bytes()).collect()
+    }
+
+    fn records(input: &str) -> Vec<Record> {
+        read(input).into_iter().map(|record| record.unwrap()).collect()
+    }
+
+    fn record(id: &str, description: &str, sequence: &str, qualities: Option<&str>) -> Record {
+        Record { id: id.to_string(), description: description.to_string(), sequence: sequence.to_string(), qualities: qualities.map(str::to_string) }
+    }
+
+    // Il primo errore di formato: (riga, messaggio)
+    fn format_error(input: &str) -> (usize, &'static str) {
+        match read(input).into_iter().find_map(Result::err) {
+            Some(FastxError::Format(line, message)) => (line, message),
+            other => panic!("expected a format error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fasta_sequences_span_many_lines() {
+        let input = ">seq1 first read\nAACGG\nTAACC\n\n>seq2\nacgtaCCC\ngt\n";
+        assert_eq!(records(input), vec![record("seq1", "first read", "AACGGTAACC", None), record("seq2", "", "acgtaCCCgt", None)]);
+    }
+
+    #[test]
+    fn fastq_qualities_may_span_lines_and_start_with_at() {
+        let input = "@read1 lane 2\nACGT\nAC\n+\n@III\nHH\n@read2\nNNaa\n+read2\nI#I#\n";
+        assert_eq!(records(input), vec![record("read1", "lane 2", "ACGTAC", Some("@IIIHH")), record("read2", "", "NNaa", Some("I#I#"))]);
+    }
+
+    #[test]
+    fn crlf_line_endings_are_removed() {
+        assert_eq!(records(">seq1 x\r\nAC\r\nGT\r\n"), vec![record("seq1", "x", "ACGT", None)]);
+        assert_eq!(records("@r\r\nACGT\r\n+\r\nIIII\r\n"), vec![record("r", "", "ACGT", Some("IIII"))]);
+    }
+
+    #[test]
+    fn blank_and_truncated_final_records() {
+        // un record senza sequenza e le righe vuote in fondo
+        assert_eq!(records(">seq1\nACGT\n>seq2\n\n\n"), vec![record("seq1", "", "ACGT", None), record("seq2", "", "", None)]);
+        // l'ultima riga senza fine riga
+        assert_eq!(records(">seq1\nACGT"), vec![record("seq1", "", "ACGT", None)]);
+
+        let input = "@r1\nACGT\n+\nIIII\n@r2\nACGT\n+\nII";
+        let read = read(input);
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].as_ref().unwrap(), &record("r1", "", "ACGT", Some("IIII")));
+        assert_eq!(format_error(input), (8, "fewer qualities than bases"));
+    }
+
+    #[test]
+    fn malformed_records_are_errors_with_their_line() {
+        assert_eq!(format_error("ACGT\n>seq1\nACGT\n"), (1, "expected a record header"));
+        assert_eq!(format_error("@r1\nACGT\n+\nIIII\n>seq1\nACGT\n"), (5, "expected a record header"));
+        assert_eq!(format_error("@r1\nACGT\nACGT\n"), (3, "missing '+' line"));
+        assert_eq!(format_error("@r1\nACGT\n+\nIII\n"), (4, "fewer qualities than bases"));
+        assert_eq!(format_error("@r1\nACGT\n+\nIIIII\n"), (4, "more qualities than bases"));
+    }
+}
@@ -5,39 +5,60 @@
 
 // suggestion: write a function find_sub(&str, &str) -> Option<(usize, &str)> that finds the first subsequence in a string, you can use it in all the following functions
 
+mod approx;
+mod fastx;
+mod multi;
+mod pattern;
+mod stream;
+mod strand;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+
+use approx::{ApproxIter, Budget, MultiApprox};
+use fastx::{FastxError, Records};
+use multi::Automaton;
+use pattern::{Ambiguity, MatchMode, Pattern, PatternError, Spacing};
+use strand::{reverse_complement, Strand, StrandSearch, Strands};
 
 // Trova la prima occorrenza della sottosequenza in una stringa
-fn find_sub<'a>(dna_sequence_left: &'a str, subsequence_to_find: &'a str) -> Option<(usize, &'a str)> {
-    if dna_sequence_left.is_empty() || subsequence_to_find.is_empty() {
-        return None;
-    }
+// La sottosequenza è un pattern come "A1-1,C2-4" (vedi pattern.rs): se non è valido non si trova niente
+// Con Spacing::Contiguous la sottosequenza è una sottostringa, con Spacing::Gapped tra i suoi elementi
+// ci possono essere altre basi; in tutti e due i casi la slice va dalla prima all'ultima base trovata
+// (le basi saltate prima di un primo elemento vuoto non ne fanno parte)
+fn find_sub<'a>(dna_sequence_left: &'a str, subsequence_to_find: &str, spacing: Spacing) -> Option<(usize, &'a str)> {
+    try_find_sub(dna_sequence_left, subsequence_to_find, spacing).ok()?
+}
+
+// Come find_sub, ma un pattern non valido è un errore invece di non trovare niente
+fn try_find_sub<'a>(dna_sequence_left: &'a str, subsequence_to_find: &str, spacing: Spacing) -> Result<Option<(usize, &'a str)>, PatternError> {
+    let pattern = Pattern::parse(subsequence_to_find)?;
+    Ok(pattern.find_spaced(dna_sequence_left, 0, spacing).map(|(start, end)| (start, &dna_sequence_left[start..end])))
+}
 
-    // Converte stringa in una collezione (vettore) di caratteri per fare il confronto crt per crt
-    let subseq_chars: Vec<char> = subsequence_to_find.chars().collect();
-    let mut subseq_idx = 0;
+pub fn demo_find_sub() {
+    let dna_sequence = "AACGGTAACC";
 
-    for (current_pos, crt_to_compare) in dna_sequence_left.chars().enumerate() {
-        if crt_to_compare == subseq_chars[subseq_idx] {
-            subseq_idx += 1;
-            if subseq_idx == subseq_chars.len() {
-                let start_pos = current_pos + 1 - subseq_chars.len();
-                return Some((start_pos, &dna_sequence_left[start_pos..=current_pos]));
-            }
+    for spacing in [Spacing::Contiguous, Spacing::Gapped] {
+        match try_find_sub(dna_sequence, "A1-1,C1-1,T1-1", spacing) {
+            Ok(Some((offset, found_subsequence))) => println!("{:?}: found subsequence at position {}: {}", spacing, offset, found_subsequence),
+            Ok(None) => println!("{:?}: subsequence not found", spacing),
+            Err(e) => return println!("Invalid pattern: {}", e),
         }
     }
-    None
 }
 
-// Trova tutte le occorrenze della sottosequenza in una stringa, senza sovrapposizioni
-fn subsequences1<'a>(dna_sequence: &'a str, subsequence_to_find: &'a str) -> Vec<(usize, &'a str)> {
+// Trova tutte le occorrenze della sottosequenza in una stringa, senza sovrapposizioni o con (vedi MatchMode)
+fn subsequences1<'a>(dna_sequence: &'a str, subsequence_to_find: &str, mode: MatchMode) -> Vec<(usize, &'a str)> {
     let mut result: Vec<(usize, &'a str)> = Vec::new(); // Vettore per memorizzare i risultati
     let mut current_pos: usize = 0; // Posizione corrente nel dna_sequence
 
     // Ciclo per trovare tutte le sottosequenze
-    while let Some((pos, found_subsequence)) = find_sub(&dna_sequence[current_pos..], subsequence_to_find) {
-        current_pos += pos;
-        result.push((current_pos, found_subsequence));
-        current_pos +=  found_subsequence.len();
+    while let Some((pos, found_subsequence)) = find_sub(&dna_sequence[current_pos..], subsequence_to_find, Spacing::Contiguous) {
+        let start = current_pos + pos;
+        result.push((start, found_subsequence));
+        current_pos = mode.resume(start, start + found_subsequence.len());
     }
 
     result 
@@ -48,44 +69,64 @@ pub fn demo1() {
     let dna_sequence = "AACGGTAACC".to_string();
     let subsequence_to_find = "A1-1,C2-4";
 
-    for (offset, found_subsequence) in subsequences1(&dna_sequence, subsequence_to_find) {
+    for (offset, found_subsequence) in subsequences1(&dna_sequence, subsequence_to_find, MatchMode::NonOverlapping) {
         println!("Found subsequence at position {}: {}", offset, found_subsequence);
     }
 }
 
+// Con le sovrapposizioni "ATA" si trova sia in posizione 0 che in posizione 2
+pub fn demo_overlapping() {
+    for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+        let found: Vec<usize> = DNAIter::new("ATATA", "ATA", mode, Strands::Forward).map(|(pos, _, _)| pos).collect();
+        println!("{:?}: found ATA at positions {:?}", mode, found);
+    }
+}
+
 // Now we want to find different subsequences at the same time, seq is a vector of string slices with many subsequence to search
 // For each subsequence find all the matches and to the results (there may be overlaps, ignore them), but in this way you can reuse the previous solution
 // The result will contain: the start position in s, the found subsequence as string slice and the mached subsequence in seq
 // Now the string slices in the rsult depend from two input parameters, which ones?
 
-fn subsequences2<'a>(dna_sequence: &'a str, subsequences_to_find: &'a [&'a str]) -> Vec<(usize, &'a str, &'a str)> {
-    let mut result: Vec<(usize, &'a str, &'a str)> = Vec::new();
-
-    // Uguale a prima, solo devo farlo per ogni sottosequenza in `subsequences_to_find`
-    for &subseq in subsequences_to_find {
-        let mut current_pos: usize = 0;
-
-        // Utilizza `find_sub` per trovare tutte le occorrenze della sottosequenza
-        while let Some((pos, found_subsequence)) = find_sub(&dna_sequence[current_pos..], subseq) {
-            current_pos += pos;
-            result.push((current_pos, found_subsequence, subseq));
-            current_pos += found_subsequence.len();
-        }
-    }
-
-    result
+// Invece di scorrere la sequenza una volta per ogni sottosequenza, un automa di Aho-Corasick (vedi multi.rs)
+// le cerca tutte insieme in una passata sola; i risultati sono in ordine di posizione
+// Le sottosequenze non valide non fermano le altre: si restituiscono a parte, ognuna con il suo errore
+// Con Strands::Both si cercano anche sul filamento complementare, ogni risultato dice dove è stato trovato
+#[allow(clippy::type_complexity)]
+fn subsequences2<'a>(dna_sequence: &'a str, subsequences_to_find: &'a [&'a str], strands: Strands) -> (Vec<(usize, &'a str, &'a str, Strand)>, Vec<(&'a str, PatternError)>) {
+    let (automaton, rejected) = Automaton::build(subsequences_to_find, strands, Ambiguity::Exact);
+    let rejected = rejected.into_iter().map(|(i, e)| (subsequences_to_find[i], e)).collect();
+    (automaton.find_all(dna_sequence, MatchMode::NonOverlapping), rejected)
 }
 
 
 pub fn demo2() {
     let dna_sequence = "AACGGTAACC".to_string();
-    let subsequences_to_find = ["A1-1,C2-4", "G1-1,T2-4"];
+    let subsequences_to_find = ["A1-1,C2-4", "G1-1,T2-4", "G1-1,X"];
 
-    for (offset, found_subsequence, subseq) in subsequences2(&dna_sequence, &subsequences_to_find) {
+    let (found, rejected) = subsequences2(&dna_sequence, &subsequences_to_find, Strands::Forward);
+    for (subseq, e) in rejected {
+        println!("Invalid subsequence '{}': {}", subseq, e);
+    }
+    for (offset, found_subsequence, subseq, _) in found {
         println!("Found subsequence '{}' at position {}: '{}'", subseq, offset, found_subsequence);
     }
 }
 
+// L'automa si costruisce una volta sola e si può usare come iteratore su più sequenze
+pub fn demo_multi_iter() {
+    let probes = ["A1-1,C2-4", "G1-2,T1-1", "CG", "ACG"];
+    let automaton = match Automaton::new(&probes, Strands::Forward) {
+        Ok(automaton) => automaton,
+        Err((i, e)) => return println!("Invalid probe {}: {}", probes[i], e),
+    };
+
+    for dna_sequence in ["AACGGTAACC", "ACGTACGTAAACCCGTACGT"] {
+        for (offset, found_subsequence, probe, _) in automaton.iter(dna_sequence, MatchMode::NonOverlapping) {
+            println!("Found probe '{}' at position {}: '{}'", probe, offset, found_subsequence);
+        }
+    }
+}
+
 // Now we want to do some DNA editing! Therefore we receive a mutable string and we'd like to return a vector of mutable string slices
 // Follow this steps:
 // 1. adjust the lifetimes without any implementation yet: does it compile?
@@ -101,25 +142,29 @@ pub fn demo2() {
 // Restituendo riferimenti mutabili a diverse parti della stringa, stai cercando di creare più di un riferimento mutabile contemporaneamente, il che viola le regole di Rust.
 
 // Soluzione alternativa 1: Restituire gli Indici, il chiamante della funzione può poi usarli per ottenere i riferimenti mutabili
-fn subsequences3<'a>(dna_sequence: &'a mut str, subsequence_to_find: &str) -> Vec<usize> {
-    let mut positions = Vec::new();
+// Le occorrenze di un pattern non hanno tutte la stessa lunghezza, quindi servono inizio e fine di ognuna
+fn subsequences3(dna_sequence: &mut str, subsequence_to_find: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let pattern = match Pattern::parse(subsequence_to_find) {
+        Ok(pattern) => pattern,
+        Err(_) => return ranges,
+    };
     let mut current_pos = 0;
 
-    while let Some(pos) = dna_sequence[current_pos..].find(subsequence_to_find) {
-        current_pos += pos;
-        positions.push(current_pos);
-        current_pos += subsequence_to_find.len();
+    while let Some((start, end)) = pattern.find(dna_sequence, current_pos) {
+        ranges.push(start..end);
+        current_pos = end;
     }
-    positions
+    ranges
 }
 
 pub fn demo3() {
     let mut dna_sequence = "AACGGTAACC".to_string();
     let subsequence_to_find = "A1-1,C2-4";
 
-    for offset in subsequences3(&mut dna_sequence, subsequence_to_find) {
-        let end_pos = offset + subsequence_to_find.len();
-        let found_subsequence = &mut dna_sequence[offset..end_pos];
+    for range in subsequences3(&mut dna_sequence, subsequence_to_find) {
+        let offset = range.start;
+        let found_subsequence = &mut dna_sequence[range];
         println!("Found subsequence at position {}: {}", offset, found_subsequence);
     }
 }
@@ -136,18 +181,27 @@ pub fn demo3() {
 // Fn: Una closure che non muta il suo stato interno.
 // FnMut: Una closure che può mutare il suo stato interno.
 // FnOnce: Una closure che può essere chiamata solo una volta, poiché consuma se stessa (e quindi il suo stato) quando viene chiamata.
-fn subsequence4<F>(dna_sequence: &str, subsequence_to_find: &str, mut process_match: F)
+fn subsequence4<F>(dna_sequence: &str, subsequence_to_find: &str, mode: MatchMode, process_match: F)
+where 
+    F: FnMut(usize, &str),
+{
+    // un pattern non valido non trova niente
+    try_subsequence4(dna_sequence, subsequence_to_find, mode, process_match).unwrap_or(());
+}
+
+// Come subsequence4, ma un pattern non valido è un errore (e la closure non viene mai chiamata)
+fn try_subsequence4<F>(dna_sequence: &str, subsequence_to_find: &str, mode: MatchMode, mut process_match: F) -> Result<(), PatternError>
 where 
     F: FnMut(usize, &str),
 {
+    let pattern = Pattern::parse(subsequence_to_find)?;
     let mut current_pos = 0;
 
-    while let Some(pos) = dna_sequence[current_pos..].find(subsequence_to_find) {
-        let start = current_pos + pos;
-        let end = start + subsequence_to_find.len();
+    while let Some((start, end)) = pattern.find(dna_sequence, current_pos) {
         process_match(start, &dna_sequence[start..end]);
-        current_pos = end;
+        current_pos = mode.resume(start, end);
     }
+    Ok(())
 }
 
 
@@ -155,9 +209,34 @@ pub fn demo4() {
     let a = "AACGGTAACC".to_string();
     let seq = "A1-1,C2-4";
     // la closure è come un array function 
-    subsequence4(&a, seq, 
+    subsequence4(&a, seq, MatchMode::NonOverlapping,
+        |pos, sub| { println!("Found subsequence at position {}: {}", pos, sub); }
+    );
+
+    let result = try_subsequence4(&a, "A1-1,X2-4", MatchMode::NonOverlapping,
         |pos, sub| { println!("Found subsequence at position {}: {}", pos, sub); }
     );
+    if let Err(e) = result {
+        println!("Invalid pattern: {}", e);
+    }
+}
+
+// Se la sequenza è così lunga da non stare in memoria non si può avere come &str: stream.rs la legge
+// a pezzi da un Read qualsiasi (un file, stdin, ...) e trova anche le occorrenze a cavallo di due pezzi
+pub fn demo_stream() {
+    let dna_sequence = "AACGGTAACC\nACCCGTACCG\n";
+    let pattern = match Pattern::parse("A1-1,C2-4") {
+        Ok(pattern) => pattern,
+        Err(e) => return println!("Invalid pattern: {}", e),
+    };
+
+    // pezzi di 4 byte, per far vedere le occorrenze che ne attraversano più di uno
+    let result = stream::search_stream(dna_sequence.as_bytes(), &pattern, MatchMode::NonOverlapping, 4, |pos, sub| {
+        println!("Found subsequence at position {}: {}", pos, sub);
+    });
+    if let Err(e) = result {
+        println!("Cannot read the sequence: {}", e);
+    }
 }
 
 // Now let's define a struct SimpleDNAIter (add the required lifetimes), memorizing a DNA sequence and the subsequence to search
@@ -168,27 +247,40 @@ pub fn demo4() {
 // This approach is similar to the previous one, but it's more flexible and it can be used in more complex scenarios. For example you may interrupt it
 // at any time and resume it later
 
+// Il pattern viene compilato una volta sola, None se non è valido (e allora non si trova niente);
+// try_new invece restituisce l'errore
 struct SimpleDNAIter<'a> {
     dna_sequence: &'a str,
-    subsequence_to_find: &'a str,
+    pattern: Option<Pattern>,
+    mode: MatchMode,
     current_pos: usize,
 }
 
 impl<'a> SimpleDNAIter<'a> {
-    pub fn new(s: &'a str, seq: &'a str) -> Self {
+    pub fn new(s: &'a str, seq: &str, mode: MatchMode) -> Self {
         SimpleDNAIter { 
             dna_sequence: s, 
-            subsequence_to_find: seq, 
+            pattern: Pattern::parse(seq).ok(),
+            mode,
             current_pos : 0
         }
     }
 
+    pub fn try_new(s: &'a str, seq: &str, mode: MatchMode) -> Result<Self, PatternError> {
+        Ok(SimpleDNAIter {
+            dna_sequence: s,
+            pattern: Some(Pattern::parse(seq)?),
+            mode,
+            current_pos: 0,
+        })
+    }
+
     // Metodo next per trovare la prossima sottosequenza
+    // (volutamente non è ancora un Iterator, lo diventa DNAIter più sotto)
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<(usize, &'a str)> {
-        if let Some(pos) = self.dna_sequence[self.current_pos..].find(self.subsequence_to_find) {
-            let start = self.current_pos + pos;
-            let end = start + self.subsequence_to_find.len();
-            self.current_pos = end;
+        if let Some((start, end)) = self.pattern.as_ref()?.find(self.dna_sequence, self.current_pos) {
+            self.current_pos = self.mode.resume(start, end);
             Some((start, &self.dna_sequence[start..end]))
         }
         else {
@@ -198,43 +290,58 @@ impl<'a> SimpleDNAIter<'a> {
 }
 
 
-fn demo_SimpleDNAIter() {
-    let mut dna_iter = SimpleDNAIter::new("ACGTACGTACGTACGT", "AC");
+fn demo_simple_dna_iter() {
+    let mut dna_iter = SimpleDNAIter::new("ACGTACGTACGTACGT", "AC", MatchMode::NonOverlapping);
 
     while let Some((pos, subseq)) = dna_iter.next() {
         println!("Found subsequence at position {}: {}", pos, subseq);
         // Possiamo interrompere il ciclo se abbiamo trovato ciò che cercavamo
     }
+
+    // con try_new un pattern non valido si vede subito, invece di non trovare niente
+    if let Err(e) = SimpleDNAIter::try_new("ACGTACGTACGTACGT", "A1-1,C3-2", MatchMode::NonOverlapping) {
+        println!("Invalid pattern: {}", e);
+    }
 }
 
 // finally we want to implement a real iterator, so that it can be used in a for loop and it may be combined we all the most common iterator methods
 // The struct DNAIter is already defined, you have to implement the Iterator trait for it and add lifetimes
+// La ricerca (su uno o su tutti e due i filamenti, vedi strand.rs) tiene la posizione corrente
 struct DNAIter<'a> {
     s: &'a str,
-    seq: &'a str,
-    current_pos: usize,  
+    search: Option<StrandSearch>,
 }
 
 impl<'a> DNAIter<'a> {
-    pub fn new(s: &'a str, seq: &'a str) -> DNAIter<'a> {
+    // Un pattern non valido non trova niente, per avere l'errore usare try_new
+    pub fn new(s: &'a str, seq: &str, mode: MatchMode, strands: Strands) -> DNAIter<'a> {
+        DNAIter {
+            s,
+            search: Pattern::parse(seq).ok().map(|pattern| StrandSearch::new(pattern, strands, mode)),
+        }
+    }
+
+    pub fn try_new(s: &'a str, seq: &str, mode: MatchMode, strands: Strands) -> Result<DNAIter<'a>, PatternError> {
+        Ok(DNAIter::with_pattern(s, Pattern::parse(seq)?, mode, strands))
+    }
+
+    // Con un pattern già compilato, per esempio per scegliere come trattare i codici ambigui nella sequenza
+    pub fn with_pattern(s: &'a str, pattern: Pattern, mode: MatchMode, strands: Strands) -> DNAIter<'a> {
         DNAIter {
-            s: s,
-            seq: seq,
-            current_pos: 0,  
+            s,
+            search: Some(StrandSearch::new(pattern, strands, mode)),
         }
     }
 }
 
 
 impl<'a> Iterator for DNAIter<'a> {
-    type Item = (usize, &'a str);
+    type Item = (usize, &'a str, Strand);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(pos) = self.s[self.current_pos..].find(self.seq) {
-            let start = self.current_pos + pos;
-            let end = start + self.seq.len();
-            self.current_pos = end; // Aggiorna la posizione corrente per la prossima chiamata a `next`
-            Some((start, &self.s[start..end]))
+        // la ricerca aggiorna da sola la posizione corrente per la prossima chiamata a `next`
+        if let Some((start, end, strand)) = self.search.as_mut()?.next(self.s) {
+            Some((start, &self.s[start..end], strand))
         } else {
             None // Nessun'altra sottosequenza trovata
         }
@@ -243,12 +350,15 @@ impl<'a> Iterator for DNAIter<'a> {
 
 
 fn demo_dna_iter() {
-    let dna_iter = DNAIter::new("ACGTACGTAAACCCGTACGT", "A1-3,C1-2");
+    let dna_iter = match DNAIter::try_new("ACGTACGTAAACCCGTACGT", "A1-3,C1-2", MatchMode::NonOverlapping, Strands::Forward) {
+        Ok(dna_iter) => dna_iter,
+        Err(e) => return println!("Invalid pattern: {}", e),
+    };
 
     // now you can combine it with all the iterator modifiers!!!
     dna_iter
-        .filter(|(pos, sub)| sub.len() >= 5)
-        .for_each(|(pos, sub)| {
+        .filter(|(_pos, sub, _strand)| sub.len() >= 5)
+        .for_each(|(pos, sub, _strand)| {
             println!(
                 "Found subsequence at least long 5 at position {}: {}",
                 pos, sub
@@ -256,42 +366,212 @@ fn demo_dna_iter() {
         });
 }
 
+// I primer hanno spesso basi degenerate: R è A o G, N una base qualsiasi
+// Anche la sequenza può avere codici ambigui (una N dove il sequenziatore non ha letto la base): di solito
+// non corrispondono a niente, con Ambiguity::Compatible corrispondono se possono essere una base cercata
+fn demo_iupac() {
+    let dna_sequence = "ACGTAGCCNGCATGAGCC";
+    let primer = "R1-1,G1-1,N2-2";
+
+    for ambiguity in [Ambiguity::Exact, Ambiguity::Compatible] {
+        let pattern = match Pattern::parse(primer) {
+            Ok(pattern) => pattern.with_ambiguity(ambiguity),
+            Err(e) => return println!("Invalid primer {}: {}", primer, e),
+        };
+        for (pos, sub, _) in DNAIter::with_pattern(dna_sequence, pattern, MatchMode::Overlapping, Strands::Forward) {
+            println!("{:?}: found primer at position {}: {}", ambiguity, pos, sub);
+        }
+    }
+}
+
 // now let's return an iterator without defining a struct, just using a closure
 // the std lib of rust support you with the std::from_fn() function
 // we supply a skeleton implementation, you have to fill the closure
-fn subsequence5_iter<'a>(s: &'a str, seq: &'a str) -> impl Iterator<Item = (usize, &'a str)> {
-    let mut pos = 0;
-    std::iter::from_fn(move || {
-        if let Some(found_pos) = s[pos..].find(seq) {
-            let start = pos + found_pos;
-            let end = start + seq.len();
-            pos = end; // Aggiorna la posizione corrente
-            Some((start, &s[start..end]))
+fn subsequence5_iter<'a>(s: &'a str, seq: &str, mode: MatchMode, strands: Strands) -> impl Iterator<Item = (usize, &'a str, Strand)> {
+    // un pattern non valido non trova niente
+    try_subsequence5_iter(s, seq, mode, strands).ok().into_iter().flatten()
+}
+
+// Come subsequence5_iter, ma un pattern non valido è un errore
+fn try_subsequence5_iter<'a>(s: &'a str, seq: &str, mode: MatchMode, strands: Strands) -> Result<impl Iterator<Item = (usize, &'a str, Strand)>, PatternError> {
+    let mut search = StrandSearch::new(Pattern::parse(seq)?, strands, mode);
+    Ok(std::iter::from_fn(move || {
+        if let Some((start, end, strand)) = search.next(s) { // la ricerca aggiorna la posizione corrente
+            Some((start, &s[start..end], strand))
         } else {
             None // Nessun'altra sottosequenza trovata
         }
-    })
+    }))
 }
 
 
 fn demo_dna_iter2() {
-    subsequence5_iter("ACGTACGTAAACCGTACGT", "ACGT")
-        .filter(|(_pos, sub)| sub.len() >= 5)
-        .for_each(|(pos, sub)| {
+    subsequence5_iter("ACGTACGTAAACCGTACGT", "ACGT", MatchMode::NonOverlapping, Strands::Forward)
+        .filter(|(_pos, sub, _strand)| sub.len() >= 5)
+        .for_each(|(pos, sub, _strand)| {
             println!(
                 "Found subsequence at least long 5 at position {}: {}",
                 pos, sub
             )
         });
+
+    if let Err(e) = try_subsequence5_iter("ACGTACGTAAACCGTACGT", "A1-1,C0-0", MatchMode::NonOverlapping, Strands::Forward) {
+        println!("Invalid pattern: {}", e);
+    }
+}
+
+// I motivi possono stare su tutti e due i filamenti: sul complementare si vedono come il loro reverse complement
+fn demo_strands() {
+    let dna_sequence = "AACGGTAACCGGTTACCG";
+    println!("Reverse complement of {}: {}", dna_sequence, reverse_complement(dna_sequence));
+
+    for (pos, sub, strand) in DNAIter::new(dna_sequence, "A1-1,C2-4", MatchMode::NonOverlapping, Strands::Both) {
+        println!("Found subsequence on strand {} at position {}: {}", strand, pos, sub);
+    }
+}
+
+
+// Le sequenze vere arrivano da file FASTA o FASTQ con molti record: si cerca in un record alla volta,
+// come con subsequence4 ogni occorrenza viene passata subito alla closure insieme al record in cui si trova
+fn search_records<R, F>(records: Records<R>, subsequence_to_find: &str, mode: MatchMode, strands: Strands, mut process_match: F) -> Result<(), FastxError>
+where
+    R: BufRead,
+    F: FnMut(&fastx::Record, usize, &str, Strand),
+{
+    for record in records {
+        let record = record?;
+        for (pos, sub, strand) in DNAIter::new(&record.sequence, subsequence_to_find, mode, strands) {
+            process_match(&record, pos, sub, strand);
+        }
+    }
+    Ok(())
+}
+
+
+fn demo_records() {
+    let fasta = ">seq1 first read\nAACGGTAACC\nacgtaCCCgt\n>seq2\nTTTTACCGG\n";
+
+    let result = search_records(Records::new(fasta.as_bytes()), "A1-1,C2-4", MatchMode::NonOverlapping, Strands::Forward, |record, pos, sub, _| {
+        println!("Found subsequence in {} at position {}: {}", record.id, pos, sub);
+    });
+    if let Err(e) = result {
+        println!("Invalid FASTA: {}", e);
+    }
 }
 
 
+// Con gli errori di sequenziamento un sito vero può differire di qualche base: si accettano occorrenze
+// con al più k sostituzioni (Hamming) o k modifiche (Levenshtein), e ognuna dice quante differenze ha
+fn demo_approx() {
+    let dna_sequence = "AACGTTAGCAATTACGATAGCCT";
+
+    for budget in [Budget::Mismatches(1), Budget::Edits(1)] {
+        for (pos, sub, distance) in ApproxIter::new(dna_sequence, "A1-1,C1-1,G1-1,T1-1,A1-1", budget, MatchMode::NonOverlapping) {
+            println!("{:?}: found subsequence at position {}: {} ({} differences)", budget, pos, sub, distance);
+        }
+    }
+
+    // ogni pattern con il suo budget
+    let patterns = [("TAGC", Budget::Mismatches(0)), ("A1-1,T2-2,C1-1,G1-1", Budget::Edits(1))];
+    match MultiApprox::new(&patterns) {
+        Ok(multi) => {
+            for (pos, sub, pattern, distance) in multi.iter(dna_sequence, MatchMode::Overlapping) {
+                println!("Found {} at position {}: {} ({} differences)", pattern, pos, sub, distance);
+            }
+        }
+        Err((i, e)) => println!("Invalid pattern {}: {}", patterns[i].0, e),
+    }
+}
+
+
+// es1 <file FASTA o FASTQ> <pattern> cerca nel file, senza argomenti si eseguono le demo
 fn main() {
-    // demo1();
-    // demo2();
-    // demo3();
-    // demo4();
-    // demo_SimpleDNAIter();
-    // demo_dna_iter();
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, path, subsequence_to_find] = args.as_slice() {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Error: cannot read {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = Pattern::parse(subsequence_to_find) {
+            eprintln!("Error: invalid pattern {}: {}", subsequence_to_find, e);
+            std::process::exit(2);
+        }
+
+        let records = Records::new(BufReader::new(file));
+        let result = search_records(records, subsequence_to_find, MatchMode::NonOverlapping, Strands::Forward, |record, pos, sub, _| {
+            println!("{}\t{}\t{}", record.id, pos, sub);
+        });
+        if let Err(e) = result {
+            eprintln!("Error: {}: {}", path, e);
+            std::process::exit(3);
+        }
+        return;
+    }
+
+    demo_find_sub();
+    demo1();
+    demo_overlapping();
+    demo2();
+    demo_multi_iter();
+    demo3();
+    demo4();
+    demo_stream();
+    demo_simple_dna_iter();
+    demo_dna_iter();
+    demo_iupac();
     demo_dna_iter2();
+    demo_strands();
+    demo_records();
+    demo_approx();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_rejected_probe_does_not_hide_the_others() {
+        let probes = ["ACG", "N1-12", "X"];
+        let (found, rejected) = subsequences2("ACGTACGT", &probes, Strands::Forward);
+        let acg: Vec<usize> = found.iter().filter(|&&(_, _, probe, _)| probe == "ACG").map(|&(pos, _, _, _)| pos).collect();
+        assert_eq!(acg, vec![0, 4]);
+        assert!(found.iter().any(|&(_, _, probe, _)| probe == "N1-12"));
+        assert_eq!(rejected, vec![("X", PatternError::BadBase(0, 'X'))]);
+    }
+
+    #[test]
+    fn overlapping_matches_start_at_every_base() {
+        let starts = |mode| subsequences1("AAAA", "AA", mode).into_iter().map(|(pos, _)| pos).collect::<Vec<_>>();
+        assert_eq!(starts(MatchMode::Overlapping), vec![0, 1, 2]);
+        assert_eq!(starts(MatchMode::NonOverlapping), vec![0, 2]);
+
+        let mut found = Vec::new();
+        subsequence4("AAAA", "AA", MatchMode::Overlapping, |pos, sub| found.push((pos, sub.to_string())));
+        assert_eq!(found, vec![(0, "AA".to_string()), (1, "AA".to_string()), (2, "AA".to_string())]);
+
+        let iter: Vec<usize> = DNAIter::new("AAAA", "AA", MatchMode::Overlapping, Strands::Forward).map(|(pos, _, _)| pos).collect();
+        assert_eq!(iter, vec![0, 1, 2]);
+        // le occorrenze sovrapposte possono avere lunghezze diverse
+        assert_eq!(subsequences1("AAAA", "A2-3", MatchMode::Overlapping), vec![(0, "AAA"), (1, "AAA"), (2, "AA")]);
+    }
+
+    #[test]
+    fn gapped_find_sub_does_not_include_skipped_bases() {
+        assert_eq!(find_sub("GGGGC", "A0-1,C", Spacing::Gapped), Some((4, "C")));
+        assert_eq!(find_sub("AxxCGxT", "ACGT", Spacing::Gapped), Some((0, "AxxCGxT")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors_with_try() {
+        let s = "ACGTACGT";
+        assert_eq!(try_find_sub(s, "C1-1,G1-1", Spacing::Contiguous), Ok(Some((1, "CG"))));
+        assert_eq!(try_find_sub(s, "C1-1,X1-1", Spacing::Contiguous), Err(PatternError::BadBase(5, 'X')));
+        assert!(try_subsequence4(s, "", MatchMode::NonOverlapping, |_, _| panic!("no pattern")).is_err());
+        assert!(SimpleDNAIter::try_new(s, "A2-1", MatchMode::NonOverlapping).is_err());
+        assert!(DNAIter::try_new(s, "A1-1,", MatchMode::NonOverlapping, Strands::Both).is_err());
+        assert!(try_subsequence5_iter(s, "Z", MatchMode::NonOverlapping, Strands::Forward).is_err());
+    }
 }
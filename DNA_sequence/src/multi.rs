@@ -0,0 +1,308 @@
+// Search for many patterns at once with an Aho-Corasick automaton
+// Each pattern is expanded into the strings it can match (see Pattern::variants), all of them go into
+// one trie whose failure links let the sequence be scanned a single time, whatever the number of patterns
+// The automaton finds the matches by their end; they are held back until no longer match can start
+// at the same position, then given out ordered by position (and pattern, for the same position),
+// with the same leftmost greedy occurrences that a search for each single pattern would find
+// With Strands::Both the reverse complement of every pattern goes into the trie too, and each hit tells
+// on which strand it was found (see strand.rs); a palindromic site is reported once, on the forward strand
+// A pattern with too many variants for the trie (a primer with many N's) is not refused: it is tried
+// on its own at every position while the sequence is scanned, and its hits are merged with the others
+// The trie only knows A, C, G and T, so with Ambiguity::Compatible (a code in the sequence matching any
+// of its bases) every pattern is tried on its own in the same way
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::pattern::{Ambiguity, MatchMode, Pattern, PatternError};
+use crate::strand::{Strand, Strands};
+
+// Limite alle stringhe in cui si può espandere un singolo pattern, oltre si cerca da solo
+const MAX_VARIANTS: usize = 1 << 16;
+
+const ROOT: usize = 0;
+
+// Una stringa che finisce in un nodo: il pattern da cui viene e su quale filamento, la sua lunghezza
+// e la preferenza della ricerca greedy (più bassa è, più è preferita)
+#[derive(Debug, Clone, Copy)]
+struct Output {
+    pattern: usize,
+    strand: Strand,
+    len: usize,
+    rank: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    next: [usize; 4],       // transizioni per A, C, G, T, già completate con i failure link
+    outputs: Vec<Output>,   // stringhe che finiscono qui, comprese quelle dei suffissi
+}
+
+pub struct Automaton<'p> {
+    patterns: Vec<&'p str>,
+    nodes: Vec<Node>,
+    max_len: usize,
+    unexpanded: Vec<(usize, Strand, Pattern)>,  // pattern (con il filamento) che non stanno nel trie
+}
+
+// Iteratore sulle occorrenze di tutti i pattern, come DNAIter: (posizione, slice trovata, pattern, filamento)
+pub struct MultiDNAIter<'m, 'a, 'p> {
+    automaton: &'m Automaton<'p>,
+    s: &'a str,
+    mode: MatchMode,
+    node: usize,                                // stato dell'automa dopo le basi già lette
+    current_pos: usize,                         // prossima base da leggere
+    pending: BTreeMap<(usize, usize, Strand), Output>,  // (inizio, pattern, filamento) -> occorrenza migliore finora
+    next_allowed: Vec<[usize; 2]>,                      // per ogni pattern e filamento, da dove può iniziare la prossima
+    last_forward: Vec<Option<(usize, usize)>>,          // per ogni pattern, l'ultima occorrenza data sul filamento diretto
+    ready: VecDeque<(usize, &'a str, &'p str, Strand)>,
+}
+
+impl<'p> Automaton<'p> {
+    // Costruisce l'automa, o restituisce l'indice del primo pattern non valido con l'errore
+    pub fn new(patterns: &[&'p str], strands: Strands) -> Result<Automaton<'p>, (usize, PatternError)> {
+        let (automaton, rejected) = Automaton::build(patterns, strands, Ambiguity::Exact);
+        match rejected.into_iter().next() {
+            Some(rejected) => Err(rejected),
+            None => Ok(automaton),
+        }
+    }
+
+    // Costruisce l'automa con i pattern validi, e restituisce anche gli altri (indice ed errore) invece di
+    // rinunciare a tutti; ogni pattern viene letto una volta sola
+    pub fn build(patterns: &[&'p str], strands: Strands, ambiguity: Ambiguity) -> (Automaton<'p>, Vec<(usize, PatternError)>) {
+        let mut nodes = vec![Node { next: [ROOT; 4], outputs: Vec::new() }];
+        let mut max_len = 0;
+        let mut unexpanded = Vec::new();
+        let mut rejected = Vec::new();
+
+        // trie con tutte le varianti di tutti i pattern
+        for (i, p) in patterns.iter().enumerate() {
+            let pattern = match Pattern::parse(p) {
+                Ok(pattern) => pattern.with_ambiguity(ambiguity),
+                Err(e) => {
+                    rejected.push((i, e));
+                    continue;
+                }
+            };
+
+            for &strand in strands.strands() {
+                let pattern = match strand {
+                    Strand::Forward => pattern.clone(),
+                    Strand::Reverse => pattern.reverse_complement(),
+                };
+                // troppe varianti, o codici nella sequenza che il trie non sa leggere
+                let variants = match pattern.variants(MAX_VARIANTS) {
+                    Ok(variants) if ambiguity == Ambiguity::Exact => variants,
+                    _ => {
+                        unexpanded.push((i, strand, pattern));
+                        continue;
+                    }
+                };
+                max_len = max_len.max(pattern.max_len());
+
+                for (rank, variant) in variants.iter().enumerate() {
+                    let mut node = ROOT;
+                    for &b in variant.iter() {
+                        let c = index(b).unwrap_or(0);
+                        if nodes[node].next[c] == ROOT {
+                            nodes.push(Node { next: [ROOT; 4], outputs: Vec::new() });
+                            nodes[node].next[c] = nodes.len() - 1;
+                        }
+                        node = nodes[node].next[c];
+                    }
+                    nodes[node].outputs.push(Output { pattern: i, strand, len: variant.len(), rank });
+                }
+            }
+        }
+
+        // visita in ampiezza: il failure link di un nodo è già pronto quando servono i suoi figli
+        let mut fail = vec![ROOT; nodes.len()];
+        let mut queue: VecDeque<usize> = nodes[ROOT].next.iter().copied().filter(|&n| n != ROOT).collect();
+        while let Some(node) = queue.pop_front() {
+            let inherited = nodes[fail[node]].outputs.clone();
+            nodes[node].outputs.extend(inherited);
+
+            for c in 0..4 {
+                let child = nodes[node].next[c];
+                let target = nodes[fail[node]].next[c];
+                if child == ROOT {
+                    nodes[node].next[c] = target;
+                } else {
+                    fail[child] = target;
+                    queue.push_back(child);
+                }
+            }
+        }
+
+        (Automaton { patterns: patterns.to_vec(), nodes, max_len, unexpanded }, rejected)
+    }
+
+    // Tutte le occorrenze in s, in ordine di posizione
+    pub fn find_all<'a>(&self, s: &'a str, mode: MatchMode) -> Vec<(usize, &'a str, &'p str, Strand)> {
+        self.iter(s, mode).collect()
+    }
+
+    pub fn iter<'m, 'a>(&'m self, s: &'a str, mode: MatchMode) -> MultiDNAIter<'m, 'a, 'p> {
+        MultiDNAIter {
+            automaton: self,
+            s,
+            mode,
+            node: ROOT,
+            current_pos: 0,
+            pending: BTreeMap::new(),
+            next_allowed: vec![[0; 2]; self.patterns.len()],
+            last_forward: vec![None; self.patterns.len()],
+            ready: VecDeque::new(),
+        }
+    }
+}
+
+impl<'m, 'a, 'p> MultiDNAIter<'m, 'a, 'p> {
+    // Sposta in ready le occorrenze che iniziano prima di `limit`, scartando quelle che si sovrappongono
+    // a una precedente dello stesso pattern sullo stesso filamento se il modo non lo permette
+    // Un sito palindromo si trova su tutti e due i filamenti con gli stessi estremi: si dà solo quello
+    // sul filamento diretto, che viene prima a parità di inizio
+    fn release(&mut self, limit: usize) {
+        while let Some(entry) = self.pending.first_entry() {
+            let (start, pattern, strand) = *entry.key();
+            if start >= limit {
+                break;
+            }
+            let output = entry.remove();
+
+            let next_allowed = &mut self.next_allowed[pattern][strand as usize];
+            if start >= *next_allowed {
+                let end = start + output.len;
+                *next_allowed = self.mode.resume(start, end);
+                match strand {
+                    Strand::Forward => self.last_forward[pattern] = Some((start, end)),
+                    Strand::Reverse if self.last_forward[pattern] == Some((start, end)) => continue,
+                    Strand::Reverse => {}
+                }
+                self.ready.push_back((start, &self.s[start..end], self.automaton.patterns[pattern], strand));
+            }
+        }
+    }
+}
+
+impl<'m, 'a, 'p> Iterator for MultiDNAIter<'m, 'a, 'p> {
+    type Item = (usize, &'a str, &'p str, Strand);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.s.as_bytes();
+
+        while self.ready.is_empty() {
+            if self.current_pos == bytes.len() {
+                self.release(usize::MAX);
+                return self.ready.pop_front();
+            }
+
+            // un carattere che non è una base non compare in nessun pattern: si riparte dalla radice
+            let nodes = &self.automaton.nodes;
+            self.node = match index(bytes[self.current_pos]) {
+                Some(c) => nodes[self.node].next[c],
+                None => ROOT,
+            };
+            self.current_pos += 1;
+
+            for output in nodes[self.node].outputs.iter() {
+                let start = self.current_pos - output.len;
+                let best = self.pending.entry((start, output.pattern, output.strand)).or_insert(*output);
+                if output.rank < best.rank {
+                    *best = *output;
+                }
+            }
+
+            // i pattern fuori dal trie si provano dalla base appena letta, l'occorrenza è già quella greedy
+            let start = self.current_pos - 1;
+            for (pattern, strand, unexpanded) in self.automaton.unexpanded.iter() {
+                if let Some(end) = unexpanded.match_at(bytes, start) {
+                    let output = Output { pattern: *pattern, strand: *strand, len: end - start, rank: 0 };
+                    self.pending.insert((start, *pattern, *strand), output);
+                }
+            }
+
+            // nessuna occorrenza che inizia prima di qui può ancora cambiare
+            self.release((self.current_pos + 1).saturating_sub(self.automaton.max_len));
+        }
+
+        self.ready.pop_front()
+    }
+}
+
+// Indice di una base nelle transizioni, minuscole comprese
+// I codici ambigui nella sequenza non corrispondono a nessuna base, come con Ambiguity::Exact
+fn index(b: u8) -> Option<usize> {
+    match b.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' | b'U' => Some(3),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Le occorrenze di ogni pattern cercato da solo, in ordine di posizione e pattern
+    fn one_by_one<'a, 'p>(s: &'a str, patterns: &[&'p str], mode: MatchMode, ambiguity: Ambiguity) -> Vec<(usize, &'a str, &'p str, Strand)> {
+        let mut found = Vec::new();
+        for &p in patterns {
+            let pattern = Pattern::parse(p).unwrap().with_ambiguity(ambiguity);
+            let mut pos = 0;
+            while let Some((start, end)) = pattern.find(s, pos) {
+                found.push((start, &s[start..end], p, Strand::Forward));
+                pos = mode.resume(start, end);
+            }
+        }
+        found.sort_by_key(|&(start, _, p, _)| (start, patterns.iter().position(|&q| q == p)));
+        found
+    }
+
+    #[test]
+    fn patterns_with_too_many_variants_are_searched_on_their_own() {
+        let patterns = ["ACG", "N1-12", "A1-1,N8-9,T1-1", "C2-3"];
+        let s = "ACGTTACCGNNACGTACCCGTTAGCATGCATTTTACGGT";
+        let automaton = Automaton::new(&patterns, Strands::Forward).unwrap();
+        assert_eq!(automaton.unexpanded.len(), 2);
+
+        for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+            assert_eq!(automaton.find_all(s, mode), one_by_one(s, &patterns, mode, Ambiguity::Exact), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn invalid_patterns_are_returned_and_the_others_searched() {
+        let patterns = ["ACG", "A1-1,X", "CG", ""];
+        let (automaton, rejected) = Automaton::build(&patterns, Strands::Forward, Ambiguity::Exact);
+        assert_eq!(rejected, vec![(1, PatternError::BadBase(5, 'X')), (3, PatternError::Empty)]);
+
+        let s = "ACGTTACGA";
+        assert_eq!(automaton.find_all(s, MatchMode::Overlapping), one_by_one(s, &["ACG", "CG"], MatchMode::Overlapping, Ambiguity::Exact));
+    }
+
+    #[test]
+    fn palindromic_sites_are_found_once() {
+        let automaton = Automaton::new(&["GAATTC", "AAC"], Strands::Both).unwrap();
+        assert_eq!(
+            automaton.find_all("GTTGAATTCAAC", MatchMode::NonOverlapping),
+            vec![(0, "GTT", "AAC", Strand::Reverse), (3, "GAATTC", "GAATTC", Strand::Forward), (9, "AAC", "AAC", Strand::Forward)]
+        );
+    }
+
+    // Con Ambiguity::Compatible i codici nella sequenza corrispondono come nella ricerca di un pattern da solo
+    #[test]
+    fn compatible_codes_in_the_sequence_match_like_a_single_search() {
+        let patterns = ["ACG", "R1-2,C1-1", "G1-1,N2-2", "T"];
+        let s = "ACGRNCGTYAKMGNNRCA";
+        for ambiguity in [Ambiguity::Exact, Ambiguity::Compatible] {
+            let (automaton, rejected) = Automaton::build(&patterns, Strands::Forward, ambiguity);
+            assert!(rejected.is_empty());
+            for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+                assert_eq!(automaton.find_all(s, mode), one_by_one(s, &patterns, mode, ambiguity), "{:?} {:?}", ambiguity, mode);
+            }
+        }
+    }
+}
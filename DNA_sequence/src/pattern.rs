@@ -0,0 +1,473 @@
+// Run-length patterns used by all the searches: "A1-1,C2-4" means one A followed by two to four C
+// Each item is a base followed by <min>-<max>, items are separated by commas
+// A base without counts stands for itself once, so "ACGT" is the same as "A1-1,C1-1,G1-1,T1-1"
+// The match is the leftmost one and each item takes as many bases as it can (greedy)
+// Counts go up to MAX_COUNT, so that the length of a match stays reasonable
+// The search backtracks when the rest of the pattern doesn't fit, but remembers which (position, item)
+// pairs already failed or succeeded, so wide ranges ("N0-1000,N0-1000,T") take polynomial time
+// Items are contiguous, or with Spacing::Gapped other bases may sit between them: "ACGT" then matches
+// the A, C, G and T of "AxxCGxT" and the reported span goes from the first to the last matched base
+// (bases can only be skipped once an item has taken some, so an empty first item never widens the span)
+// Bases can be IUPAC codes: "R2-3" means two or three bases among A and G, "N" any base
+// With Ambiguity::Exact (the default) only A, C, G and T in the sequence can match, an N there matches nothing;
+// with Ambiguity::Compatible a code in the sequence matches if it could be one of the bases of the pattern
+// Lowercase bases in the sequence (soft-masked) match like uppercase ones
+// Positions are byte offsets in the sequence, so the spans can always be used to slice it
+// Searches for all the occurrences either skip past each one or, with MatchMode::Overlapping,
+// look for the next one from the base after its start
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Insiemi di basi come maschere di bit, per i codici IUPAC
+const A: u8 = 1;
+const C: u8 = 2;
+const G: u8 = 4;
+const T: u8 = 8;
+const BASES: [(u8, u8); 4] = [(A, b'A'), (C, b'C'), (G, b'G'), (T, b'T')];
+
+// Massimo numero di ripetizioni di un elemento
+pub const MAX_COUNT: usize = 1000;
+
+// Un elemento del pattern: le basi ammesse e quante volte devono ripetersi
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Run {
+    bases: u8,
+    min: usize,
+    max: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    runs: Vec<Run>,
+    ambiguity: Ambiguity,
+}
+
+// Se i codici ambigui nella sequenza possono corrispondere al pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ambiguity {
+    Exact,          // no: nella sequenza contano solo A, C, G e T
+    Compatible,     // sì, se possono essere una delle basi ammesse
+}
+
+// Come possono stare gli elementi del pattern nella sequenza
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spacing {
+    Contiguous,     // uno subito dopo l'altro
+    Gapped,         // in ordine, ma con altre basi in mezzo
+}
+
+// Dove riprende la ricerca dopo un'occorrenza
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchMode {
+    NonOverlapping, // dopo la fine dell'occorrenza
+    Overlapping,    // dalla base dopo il suo inizio, così "ATA" si trova due volte in "ATATA"
+}
+
+// Errori di parsing, con la posizione (in byte) nel pattern
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternError {
+    Empty,                          // pattern vuoto, o che può trovare la stringa vuota
+    BadBase(usize, char),           // carattere che non è un codice IUPAC
+    BadCount(usize),                // conteggio mancante o non numerico dopo la base
+    BadRange(usize, usize, usize),  // min > max, oppure max = 0 o più di MAX_COUNT
+    TooManyVariants(usize),         // il pattern corrisponde a più stringhe del limite dato
+    TooManyEdits(usize),            // con k modifiche anche la stringa vuota sarebbe un'occorrenza
+}
+
+impl Pattern {
+    // Compila il pattern, controllando la sintassi
+    pub fn parse(s: &str) -> Result<Pattern, PatternError> {
+        let bytes = s.as_bytes();
+        let mut runs = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            // le virgole separano gli elementi, ma non possono stare all'inizio o ripetersi
+            if bytes[i] == b',' && !runs.is_empty() && i + 1 < bytes.len() && bytes[i + 1] != b',' {
+                i += 1;
+            }
+
+            let bases = match code(bytes[i]) {
+                Some(bases) => bases,
+                None => return Err(PatternError::BadBase(i, s[i..].chars().next().unwrap_or('?'))),
+            };
+            let at = i;
+            i += 1;
+
+            // senza cifre la base vale per se stessa, una volta sola
+            if i == bytes.len() || !bytes[i].is_ascii_digit() {
+                runs.push(Run { bases, min: 1, max: 1 });
+                continue;
+            }
+            let min = number(bytes, &mut i).ok_or(PatternError::BadCount(i))?;
+            if i == bytes.len() || bytes[i] != b'-' {
+                return Err(PatternError::BadCount(i));
+            }
+            i += 1;
+            let max = number(bytes, &mut i).ok_or(PatternError::BadCount(i))?;
+            if min > max || max == 0 || max > MAX_COUNT {
+                return Err(PatternError::BadRange(at, min, max));
+            }
+            runs.push(Run { bases, min, max });
+        }
+
+        if runs.iter().all(|run| run.min == 0) {
+            return Err(PatternError::Empty);
+        }
+        Ok(Pattern { runs, ambiguity: Ambiguity::Exact })
+    }
+
+    // Lo stesso pattern, con un altro modo di trattare i codici ambigui nella sequenza
+    pub fn with_ambiguity(self, ambiguity: Ambiguity) -> Pattern {
+        Pattern { ambiguity, ..self }
+    }
+
+    // Prima occorrenza che inizia da `from` in poi: (inizio, fine) in byte
+    // Il risultato di un elemento in una posizione non dipende dall'inizio, quindi vale per tutti gli inizi
+    pub fn find(&self, seq: &str, from: usize) -> Option<(usize, usize)> {
+        let seq = seq.as_bytes();
+        let mut memo = BTreeMap::new();
+        for start in from..seq.len() {
+            // le posizioni prima dell'inizio non si vedranno più
+            memo = memo.split_off(&(start, 0));
+            if let Some(end) = self.match_runs(seq, start, 0, &mut memo) {
+                return Some((start, end));
+            }
+        }
+        None
+    }
+
+    // Come find, ma scegliendo se gli elementi devono essere contigui
+    pub fn find_spaced(&self, seq: &str, from: usize, spacing: Spacing) -> Option<(usize, usize)> {
+        if spacing == Spacing::Contiguous {
+            return self.find(seq, from);
+        }
+
+        let seq = seq.as_bytes();
+        let first = &self.runs[0];
+        let mut memo = BTreeMap::new();
+        for start in from..seq.len() {
+            memo = memo.split_off(&(start, 0, false));
+            if let Some(end) = self.match_gapped(seq, start, start, 0, &mut memo) {
+                return Some((start, end));
+            }
+            // se il primo elemento c'era ma il resto no, più avanti ci sarebbe ancora meno spazio
+            if first.min > 0 && first.count(seq, start, self.ambiguity) >= first.min {
+                return None;
+            }
+        }
+        None
+    }
+
+    // Il pattern come si legge sull'altro filamento: elementi in ordine inverso e basi complementari
+    // Cercarlo nella sequenza trova il pattern sul filamento opposto, con le posizioni del filamento diretto
+    pub fn reverse_complement(&self) -> Pattern {
+        let runs = self.runs.iter().rev().map(|run| Run { bases: complement(run.bases), ..*run }).collect();
+        Pattern { runs, ambiguity: self.ambiguity }
+    }
+
+    // Lunghezza massima di un'occorrenza (usize::MAX se non ci sta, con moltissimi elementi)
+    pub fn max_len(&self) -> usize {
+        self.runs.iter().try_fold(0usize, |len, run| len.checked_add(run.max)).unwrap_or(usize::MAX)
+    }
+
+    // Le forme del pattern, una per ogni scelta dei conteggi: per ogni posizione le basi ammesse
+    // Sono nell'ordine in cui le preferisce la ricerca greedy, prima i conteggi più alti
+    pub fn shapes(&self, limit: usize) -> Result<Vec<Vec<u8>>, PatternError> {
+        let mut shapes = vec![Vec::new()];
+        for run in self.runs.iter() {
+            let mut next = Vec::new();
+            for prefix in shapes.iter() {
+                for n in (run.min..=run.max).rev() {
+                    if next.len() == limit {
+                        return Err(PatternError::TooManyVariants(limit));
+                    }
+                    let mut shape = prefix.clone();
+                    shape.resize(prefix.len() + n, run.bases);
+                    next.push(shape);
+                }
+            }
+            shapes = next;
+        }
+        Ok(shapes)
+    }
+
+    // Tutte le stringhe che il pattern può trovare, nell'ordine in cui le preferisce la ricerca greedy:
+    // a parità di inizio, l'occorrenza trovata da find è la prima di queste che corrisponde
+    pub fn variants(&self, limit: usize) -> Result<Vec<Vec<u8>>, PatternError> {
+        let mut variants = Vec::new();
+        for shape in self.shapes(limit)? {
+            // tutte le stringhe con una delle basi ammesse in ogni posizione
+            let mut strings = vec![Vec::new()];
+            for &bases in shape.iter() {
+                let mut longer = Vec::new();
+                for string in strings.iter() {
+                    for &(_, b) in BASES.iter().filter(|(bit, _)| bases & bit != 0) {
+                        let mut string = string.clone();
+                        string.push(b);
+                        longer.push(string);
+                    }
+                }
+                strings = longer;
+                if variants.len() + strings.len() > limit {
+                    return Err(PatternError::TooManyVariants(limit));
+                }
+            }
+            variants.extend(strings);
+        }
+
+        // la stessa stringa può venire da conteggi diversi ("A1-2,A1-2"): vale la prima
+        let mut seen = std::collections::HashSet::new();
+        variants.retain(|v| seen.insert(v.clone()));
+        Ok(variants)
+    }
+
+    pub fn ambiguity(&self) -> Ambiguity {
+        self.ambiguity
+    }
+
+    // Fine della corrispondenza più lunga che inizia esattamente in `start`
+    pub fn match_at(&self, seq: &[u8], start: usize) -> Option<usize> {
+        self.match_runs(seq, start, 0, &mut BTreeMap::new())
+    }
+
+    // Ogni elemento prende più basi che può, se poi il resto non corrisponde ne restituisce una alla volta
+    // memo tiene per (posizione, elemento) la fine già trovata, o None se da lì il resto non corrisponde
+    fn match_runs(&self, seq: &[u8], pos: usize, i: usize, memo: &mut BTreeMap<(usize, usize), Option<usize>>) -> Option<usize> {
+        let run = match self.runs.get(i) {
+            Some(run) => run,
+            None => return Some(pos),
+        };
+        if let Some(&end) = memo.get(&(pos, i)) {
+            return end;
+        }
+
+        let available = run.count(seq, pos, self.ambiguity);
+        let end = if available < run.min {
+            None
+        } else {
+            (run.min..=available).rev().find_map(|n| self.match_runs(seq, pos + n, i + 1, memo))
+        };
+        memo.insert((pos, i), end);
+        end
+    }
+
+    // Come match_runs, ma prima di ogni elemento si possono saltare delle basi, se gli elementi precedenti
+    // ne hanno già presa almeno una: l'occorrenza inizia sempre con una base trovata
+    // Basta provare la prima posizione in cui l'elemento si trova: più avanti il resto avrebbe meno spazio
+    // In memo la chiave dice anche se si possono saltare basi, l'unica cosa che dipende dall'inizio
+    fn match_gapped(&self, seq: &[u8], start: usize, pos: usize, i: usize, memo: &mut BTreeMap<(usize, usize, bool), Option<usize>>) -> Option<usize> {
+        let run = match self.runs.get(i) {
+            Some(run) => run,
+            None => return Some(pos),
+        };
+        let gaps = pos > start;
+        if let Some(&end) = memo.get(&(pos, i, gaps)) {
+            return end;
+        }
+
+        let last = if gaps { seq.len() } else { pos };
+        let end = (pos..=last).find(|&q| run.count(seq, q, self.ambiguity) >= run.min).and_then(|q| {
+            (run.min..=run.count(seq, q, self.ambiguity)).rev().find_map(|n| self.match_gapped(seq, start, q + n, i + 1, memo))
+        });
+        memo.insert((pos, i, gaps), end);
+        end
+    }
+}
+
+impl Run {
+    // Quante basi ammesse dall'elemento ci sono da `pos` in poi, al massimo max
+    fn count(&self, seq: &[u8], pos: usize, ambiguity: Ambiguity) -> usize {
+        seq[pos..].iter().take(self.max).take_while(|&&b| accepts(self.bases, b, ambiguity)).count()
+    }
+}
+
+impl MatchMode {
+    // Posizione da cui cercare l'occorrenza successiva a quella in start..end
+    pub fn resume(self, start: usize, end: usize) -> usize {
+        match self {
+            MatchMode::NonOverlapping => end,
+            MatchMode::Overlapping => start + 1,
+        }
+    }
+}
+
+// Basi rappresentate da un codice IUPAC (U è la T dell'RNA), le minuscole sono basi mascherate e valgono come le maiuscole
+pub fn code(b: u8) -> Option<u8> {
+    let bases = match b.to_ascii_uppercase() {
+        b'A' => A,
+        b'C' => C,
+        b'G' => G,
+        b'T' | b'U' => T,
+        b'R' => A | G,
+        b'Y' => C | T,
+        b'S' => C | G,
+        b'W' => A | T,
+        b'K' => G | T,
+        b'M' => A | C,
+        b'B' => C | G | T,
+        b'D' => A | G | T,
+        b'H' => A | C | T,
+        b'V' => A | C | G,
+        b'N' => A | C | G | T,
+        _ => return None,
+    };
+    Some(bases)
+}
+
+// Se il carattere b della sequenza può essere una delle basi ammesse
+pub fn accepts(bases: u8, b: u8, ambiguity: Ambiguity) -> bool {
+    match (code(b), ambiguity) {
+        (Some(code), Ambiguity::Exact) => code.count_ones() == 1 && code & bases != 0,
+        (Some(code), Ambiguity::Compatible) => code & bases != 0,
+        (None, _) => false,
+    }
+}
+
+// Basi complementari: A <-> T e C <-> G
+fn complement(bases: u8) -> u8 {
+    (bases & A) << 3 | (bases & T) >> 3 | (bases & C) << 1 | (bases & G) >> 1
+}
+
+// Legge un numero decimale a partire da `i`, spostando `i` dopo l'ultima cifra
+fn number(bytes: &[u8], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    while *i < bytes.len() && bytes[*i].is_ascii_digit() {
+        *i += 1;
+    }
+    std::str::from_utf8(&bytes[start..*i]).ok()?.parse().ok()
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatternError::Empty => write!(f, "the pattern is empty or matches the empty string"),
+            PatternError::BadBase(pos, c) => write!(f, "'{}' at position {} is not an IUPAC code", c, pos),
+            PatternError::BadCount(pos) => write!(f, "expected <min>-<max> at position {}", pos),
+            PatternError::BadRange(pos, min, max) => {
+                write!(f, "invalid range {}-{} at position {} (counts go from 0 to {})", min, max, pos, MAX_COUNT)
+            }
+            PatternError::TooManyVariants(limit) => write!(f, "the pattern matches more than {} different strings", limit),
+            PatternError::TooManyEdits(k) => write!(f, "{} edits are as many as the bases of the pattern", k),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generatore xorshift, per avere sempre gli stessi casi casuali
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick(&mut self, choices: &[u8]) -> u8 {
+            choices[self.below(choices.len())]
+        }
+    }
+
+    // Pattern casuale, come (basi, min, max) per ogni elemento e come testo
+    fn random_pattern(rng: &mut Rng) -> (Vec<(u8, usize, usize)>, String) {
+        let runs: Vec<(u8, usize, usize)> = (0..1 + rng.below(3))
+            .map(|_| {
+                let min = rng.below(3);
+                (rng.pick(b"ACGTNRY"), min, (min + rng.below(3)).max(1))
+            })
+            .collect();
+        let text: Vec<String> = runs.iter().map(|&(b, min, max)| format!("{}{}-{}", b as char, min, max)).collect();
+        (runs, text.join(","))
+    }
+
+    // Se le n basi da pos ci sono e sono tutte ammesse dall'elemento
+    fn fits(seq: &[u8], pos: usize, n: usize, base: u8) -> bool {
+        let bases = code(base).unwrap_or(0);
+        pos + n <= seq.len() && seq[pos..pos + n].iter().all(|&b| accepts(bases, b, Ambiguity::Exact))
+    }
+
+    // Riferimento ingenuo: si provano tutti i conteggi (e, se gapped, tutti i salti prima di ogni elemento
+    // dopo la prima base presa) nell'ordine della ricerca greedy: salti più corti, poi conteggi più alti
+    fn naive_end(runs: &[(u8, usize, usize)], seq: &[u8], start: usize, pos: usize, gapped: bool) -> Option<usize> {
+        let (&(base, min, max), rest) = match runs.split_first() {
+            Some(split) => split,
+            None => return Some(pos),
+        };
+        let gaps = if gapped && pos > start { seq.len() - pos } else { 0 };
+
+        (pos..=pos + gaps).find_map(|p| {
+            (min..=max).rev().filter(|&n| fits(seq, p, n, base)).find_map(|n| naive_end(rest, seq, start, p + n, gapped))
+        })
+    }
+
+    fn naive_find(runs: &[(u8, usize, usize)], seq: &[u8], from: usize, gapped: bool) -> Option<(usize, usize)> {
+        (from..seq.len()).find_map(|start| naive_end(runs, seq, start, start, gapped).map(|end| (start, end)))
+    }
+
+    // Con un primo elemento vuoto l'occorrenza inizia dalla prima base presa, non da quelle saltate
+    #[test]
+    fn gapped_span_starts_at_the_first_matched_base() {
+        let find = |seq: &str, pattern: &str| Pattern::parse(pattern).unwrap().find_spaced(seq, 0, Spacing::Gapped);
+        assert_eq!(find("GGGGC", "A0-1,C"), Some((4, 5)));
+        assert_eq!(find("GAGC", "A0-1,C"), Some((1, 4)));
+        assert_eq!(find("GGAxxC", "T0-2,A1-1,C1-1"), Some((2, 6)));
+        assert_eq!(find("ACxxG", "A0-1,C0-1,G"), Some((0, 5)));
+    }
+
+    #[test]
+    fn contiguous_and_gapped_match_the_naive_search() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..20_000 {
+            let (runs, text) = random_pattern(&mut rng);
+            let pattern = match Pattern::parse(&text) {
+                Ok(pattern) => pattern,
+                Err(_) => continue,
+            };
+            let seq: String = (0..rng.below(25)).map(|_| rng.pick(b"ACGTNacgt") as char).collect();
+            let from = rng.below(seq.len() + 1);
+
+            for (spacing, gapped) in [(Spacing::Contiguous, false), (Spacing::Gapped, true)] {
+                assert_eq!(
+                    pattern.find_spaced(&seq, from, spacing),
+                    naive_find(&runs, seq.as_bytes(), from, gapped),
+                    "{:?} {} in {} from {}",
+                    spacing,
+                    text,
+                    seq,
+                    from
+                );
+            }
+        }
+    }
+
+    // Senza ricordare i risultati, ogni conteggio del primo elemento riproverebbe tutti quelli dei successivi
+    #[test]
+    fn wide_ranges_take_polynomial_time() {
+        let pattern = Pattern::parse("N0-1000,N0-1000,N0-1000,T").unwrap();
+        let seq = "ACG".repeat(200);
+        let started = std::time::Instant::now();
+
+        assert_eq!(pattern.find(&seq, 0), None);
+        assert_eq!(pattern.find_spaced(&seq, 0, Spacing::Gapped), None);
+        assert_eq!(pattern.find(&format!("{}T", seq), 0), Some((0, 601)));
+        assert!(started.elapsed() < std::time::Duration::from_secs(10), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn counts_are_capped() {
+        assert_eq!(Pattern::parse("A1-1000,C1-2").map(|p| p.max_len()), Ok(1002));
+        assert_eq!(Pattern::parse("A1-1001").err(), Some(PatternError::BadRange(0, 1, 1001)));
+        assert_eq!(
+            Pattern::parse("A1-18446744073709551615,C1-18446744073709551615").err(),
+            Some(PatternError::BadRange(0, 1, usize::MAX))
+        );
+        assert_eq!(Pattern::parse("A1-99999999999999999999").err(), Some(PatternError::BadCount(23)));
+    }
+}
@@ -0,0 +1,169 @@
+// Searches on both strands of the DNA
+// A motif on the reverse strand shows up on the forward one as its reverse complement, so the pattern
+// is searched as it is and reverse complemented: every hit is tagged with the strand it was found on,
+// and its position is always where it starts on the forward strand
+// Each strand is searched on its own, so with MatchMode::NonOverlapping a hit on one strand doesn't
+// hide an overlapping hit on the other one; a palindromic site like "GAATTC" reads the same on both
+// strands and is reported once, on the forward strand
+
+use std::fmt;
+
+use crate::pattern::{MatchMode, Pattern};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+// Su quali filamenti cercare
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strands {
+    Forward,    // solo quello diretto, come sempre
+    Both,       // anche quello complementare
+}
+
+// Ricerca di un pattern su uno o due filamenti, con le occorrenze in ordine di posizione
+// (a parità di posizione prima quella sul filamento diretto)
+#[derive(Debug, Clone)]
+pub struct StrandSearch {
+    searches: Vec<Search>,
+    mode: MatchMode,
+    last_forward: Option<(usize, usize)>,   // ultima occorrenza data sul filamento diretto
+}
+
+#[derive(Debug, Clone)]
+struct Search {
+    pattern: Pattern,
+    strand: Strand,
+    pos: usize,                             // da dove cercare la prossima occorrenza
+    next: Option<Option<(usize, usize)>>,   // prossima occorrenza già trovata, Some(None) se non ce ne sono più
+}
+
+impl Strands {
+    pub fn strands(self) -> &'static [Strand] {
+        match self {
+            Strands::Forward => &[Strand::Forward],
+            Strands::Both => &[Strand::Forward, Strand::Reverse],
+        }
+    }
+}
+
+impl StrandSearch {
+    pub fn new(pattern: Pattern, strands: Strands, mode: MatchMode) -> StrandSearch {
+        let searches = strands
+            .strands()
+            .iter()
+            .map(|&strand| {
+                let pattern = match strand {
+                    Strand::Forward => pattern.clone(),
+                    Strand::Reverse => pattern.reverse_complement(),
+                };
+                Search { pattern, strand, pos: 0, next: None }
+            })
+            .collect();
+        StrandSearch { searches, mode, last_forward: None }
+    }
+
+    // Prossima occorrenza in s (sempre la stessa sequenza a ogni chiamata): inizio, fine e filamento
+    // Se sul filamento complementare ha gli stessi estremi dell'ultima sul diretto è lo stesso sito palindromo,
+    // che quindi si salta (a parità di inizio il filamento diretto viene prima)
+    pub fn next(&mut self, s: &str) -> Option<(usize, usize, Strand)> {
+        loop {
+            for search in self.searches.iter_mut() {
+                if search.next.is_none() {
+                    search.next = Some(search.pattern.find(s, search.pos));
+                }
+            }
+
+            let (i, _) = self
+                .searches
+                .iter()
+                .enumerate()
+                .filter_map(|(i, search)| search.next.flatten().map(|(start, _)| (i, (start, search.strand))))
+                .min_by_key(|&(_, key)| key)?;
+            let search = &mut self.searches[i];
+            let (start, end) = search.next.take().flatten()?;
+            search.pos = self.mode.resume(start, end);
+
+            match search.strand {
+                Strand::Forward => self.last_forward = Some((start, end)),
+                Strand::Reverse if self.last_forward == Some((start, end)) => continue,
+                Strand::Reverse => {}
+            }
+            return Some((start, end, search.strand));
+        }
+    }
+}
+
+// Il filamento complementare letto nel suo verso (5' -> 3'), anche con i codici IUPAC:
+// ogni codice diventa quello delle basi complementari, le minuscole restano minuscole
+// e i caratteri che non sono codici restano come sono
+pub fn reverse_complement(seq: &str) -> String {
+    seq.chars()
+        .rev()
+        .map(|c| {
+            let complement = match c.to_ascii_uppercase() {
+                'A' => 'T',
+                'T' | 'U' => 'A',
+                'C' => 'G',
+                'G' => 'C',
+                'R' => 'Y',
+                'Y' => 'R',
+                'K' => 'M',
+                'M' => 'K',
+                'B' => 'V',
+                'V' => 'B',
+                'D' => 'H',
+                'H' => 'D',
+                'S' | 'W' | 'N' => c.to_ascii_uppercase(),
+                _ => return c,
+            };
+            if c.is_ascii_lowercase() { complement.to_ascii_lowercase() } else { complement }
+        })
+        .collect()
+}
+
+impl fmt::Display for Strand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strand::Forward => write!(f, "+"),
+            Strand::Reverse => write!(f, "-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(s: &str, pattern: &str, mode: MatchMode) -> Vec<(usize, usize, Strand)> {
+        let mut search = StrandSearch::new(Pattern::parse(pattern).unwrap(), Strands::Both, mode);
+        std::iter::from_fn(|| search.next(s)).collect()
+    }
+
+    #[test]
+    fn iupac_codes_are_complemented() {
+        assert_eq!(reverse_complement("ACGTU"), "AACGT");
+        assert_eq!(reverse_complement("RYKMN"), "NKMRY");
+        assert_eq!(reverse_complement("BVDHSW"), "WSDHBV");
+        assert_eq!(reverse_complement("acgRy-"), "-rYcgt");
+        assert_eq!(reverse_complement(&reverse_complement("ACGTRYKMBVDHSWN")), "ACGTRYKMBVDHSWN");
+    }
+
+    #[test]
+    fn reverse_hits_have_forward_positions() {
+        // AAC sul filamento complementare si legge GTT su quello diretto
+        assert_eq!(search("GTTAAC", "AAC", MatchMode::NonOverlapping), vec![(0, 3, Strand::Reverse), (3, 6, Strand::Forward)]);
+        assert_eq!(search("CCGTTTT", "A2-4,C1-1", MatchMode::NonOverlapping), vec![(2, 7, Strand::Reverse)]);
+    }
+
+    #[test]
+    fn palindromic_sites_are_found_once() {
+        for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+            assert_eq!(search("AAGAATTCAA", "GAATTC", mode), vec![(2, 8, Strand::Forward)]);
+        }
+        // occorrenze sui due filamenti che si sovrappongono ma con estremi diversi restano tutte e due
+        assert_eq!(search("AATT", "A1-1,T1-2", MatchMode::NonOverlapping), vec![(0, 3, Strand::Reverse), (1, 4, Strand::Forward)]);
+    }
+}
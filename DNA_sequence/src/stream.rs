@@ -0,0 +1,100 @@
+// Search in sequences too long to be held in memory, read a chunk at a time from any Read source
+// The bases of the last chunk that could still be the start of a match are kept and searched again
+// together with the next chunk, so matches across two chunks are found too: the memory used is at most
+// the chunk size plus the longest match of the pattern
+// Line breaks are not part of the sequence and are skipped, the positions count the bases from the
+// start of the stream
+
+use std::io::{self, Read};
+
+use crate::pattern::{MatchMode, Pattern};
+
+// Chiama process_match per ogni occorrenza, con la posizione assoluta, man mano che le trova
+pub fn search_stream<R, F>(mut reader: R, pattern: &Pattern, mode: MatchMode, chunk_size: usize, mut process_match: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(usize, &str),
+{
+    let max_len = pattern.max_len();
+    let mut chunk = vec![0; chunk_size.max(1)];
+    let mut window: Vec<u8> = Vec::with_capacity(chunk.len().saturating_add(max_len));
+    let mut offset = 0;     // posizione assoluta di window[0]
+    let mut pos = 0;        // prossimo inizio da provare in window
+
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let end_of_stream = n == 0;
+        window.extend(chunk[..n].iter().filter(|&&b| b != b'\n' && b != b'\r'));
+
+        while pos < window.len() {
+            // un'occorrenza che inizia qui potrebbe continuare nel prossimo chunk: si decide dopo
+            if !end_of_stream && pos.saturating_add(max_len) > window.len() {
+                break;
+            }
+            match pattern.match_at(&window, pos) {
+                Some(end) => {
+                    // le basi sono caratteri ASCII, quindi la slice è sempre UTF-8 valido
+                    process_match(offset + pos, std::str::from_utf8(&window[pos..end]).unwrap_or_default());
+                    pos = mode.resume(pos, end);
+                }
+                None => pos += 1,
+            }
+        }
+
+        // quello che c'è prima di pos non serve più
+        window.drain(..pos);
+        offset += pos;
+        pos = 0;
+
+        if end_of_stream {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsequences1;
+
+    fn stream(input: &str, pattern: &str, mode: MatchMode, chunk_size: usize) -> Vec<(usize, String)> {
+        let mut found = Vec::new();
+        let pattern = Pattern::parse(pattern).unwrap();
+        search_stream(input.as_bytes(), &pattern, mode, chunk_size, |pos, sub| found.push((pos, sub.to_string()))).unwrap();
+        found
+    }
+
+    // Le stesse occorrenze di find_sub sulla sequenza intera, qualunque sia la dimensione dei chunk
+    #[test]
+    fn matches_across_chunks_are_found_once() {
+        let lines = ["AACGGTAACC", "ACCCGTACCG", "", "CCAATACCCC", "A"];
+        let sequence = lines.concat();
+
+        for input in [lines.join("\n"), lines.join("\r\n") + "\r\n"] {
+            for pattern in ["A1-1,C2-4", "ACC", "C1-3,G1-1", "ATA", "N2-3,C1-1"] {
+                for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+                    let expected: Vec<(usize, String)> =
+                        subsequences1(&sequence, pattern, mode).into_iter().map(|(pos, sub)| (pos, sub.to_string())).collect();
+                    for chunk_size in 1..=4 {
+                        assert_eq!(stream(&input, pattern, mode, chunk_size), expected, "{} {:?} {}", pattern, mode, chunk_size);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_match_at_the_end_of_the_input() {
+        for chunk_size in 1..=4 {
+            for mode in [MatchMode::NonOverlapping, MatchMode::Overlapping] {
+                assert_eq!(stream("GTT\nTACC", "A1-1,C2-4", mode, chunk_size), vec![(4, "ACC".to_string())]);
+                assert_eq!(stream("GTTAC\nC\n", "A1-1,C2-4", mode, chunk_size), vec![(3, "ACC".to_string())]);
+            }
+            assert_eq!(stream("AAAA", "AA", MatchMode::Overlapping, chunk_size), vec![(0, "AA".to_string()), (1, "AA".to_string()), (2, "AA".to_string())]);
+        }
+    }
+}
@@ -0,0 +1,360 @@
+/*
+ * Computer opponents
+ * An Ai never looks at the opponent's board: it chooses where to shoot and learns the outcome,
+ * keeping what it knows in a TargetView
+ *
+ * random       shoots at random cells
+ * hunt         hunt/target: shoots at random (on a checkerboard) until a hit, then follows the boat along its axis
+ * density      counts, for every cell, the legal placements of the boats still afloat (straight boats in both
+ *              directions, pieces in all four rotations) and shoots the most likely cell
+ */
+
+use std::str::FromStr;
+
+use crate::board::{Board, Error, Pos, ShotOutcome};
+use crate::rng::Rng;
+use crate::target::{Mark, TargetView};
+
+pub trait Ai {
+    // Choose the next cell to shoot, None if there is nothing left to shoot
+    fn next_shot(&mut self) -> Option<Pos>;
+    // Learn the outcome of a shot
+    fn record(&mut self, pos: Pos, outcome: ShotOutcome);
+    // Learn the cells of the boat just sunk, when the rules reveal them
+    fn reveal(&mut self, _boat: &[Pos]) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    Random,
+    HuntTarget,
+    Density,
+}
+
+impl Strategy {
+
+    // New opponent for a board with the size, fleet and rules of the given one
+    pub fn create(&self, board: &Board, seed: u64) -> Box<dyn Ai> {
+        let view = TargetView::for_board(board);
+        let rng = Rng::new(seed);
+
+        match self {
+            Strategy::Random => Box::new(RandomAi { view, rng }),
+            Strategy::HuntTarget => Box::new(HuntTargetAi { view, rng }),
+            Strategy::Density => Box::new(DensityAi { view, rng }),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Strategy, String> {
+        match s {
+            "random" => Ok(Strategy::Random),
+            "hunt" => Ok(Strategy::HuntTarget),
+            "density" => Ok(Strategy::Density),
+            _ => Err(format!("unknown strategy '{}', expected random, hunt or density", s)),
+        }
+    }
+}
+
+//------------------------------------------------
+// Random shooting
+//------------------------------------------------
+pub struct RandomAi {
+    view: TargetView,
+    rng: Rng,
+}
+
+impl Ai for RandomAi {
+    fn next_shot(&mut self) -> Option<Pos> {
+        let cells: Vec<Pos> = self
+            .view
+            .cells()
+            .filter(|p| matches!(self.view.get(*p), Mark::Unknown | Mark::Water))
+            .collect();
+
+        pick(&mut self.rng, &cells)
+    }
+
+    fn record(&mut self, pos: Pos, outcome: ShotOutcome) {
+        self.view.record(pos, outcome);
+    }
+
+    fn reveal(&mut self, boat: &[Pos]) {
+        self.view.reveal(boat);
+    }
+}
+
+//------------------------------------------------
+// Hunt / target
+//------------------------------------------------
+pub struct HuntTargetAi {
+    view: TargetView,
+    rng: Rng,
+}
+
+impl HuntTargetAi {
+
+    // Cells to try to finish the boat in hit: the two ends of the line if its axis is known, otherwise its sides
+    // Only the cells that a boat still afloat can cover together with the hits are kept (pieces may bend
+    // away from the line), unless there is none of them
+    fn target_cells(&self, hit: Pos) -> Vec<Pos> {
+        let group = self.view.hit_group(hit);
+        let density = density(&self.view);
+        let unknown = |p: &Pos| self.view.get(*p) == Mark::Unknown;
+        let covered = |p: &Pos| density[p.x][p.y] > 0;
+
+        if group.len() >= 2 {
+            let (min_x, max_x) = min_max(group.iter().map(|p| p.x));
+            let (min_y, max_y) = min_max(group.iter().map(|p| p.y));
+            let mut ends = Vec::new();
+
+            if min_x == max_x {
+                // horizontal boat
+                if min_y > 0 {
+                    ends.push(Pos { x: min_x, y: min_y - 1 });
+                }
+                ends.push(Pos { x: min_x, y: max_y + 1 });
+            } else if min_y == max_y {
+                // vertical boat
+                if min_x > 0 {
+                    ends.push(Pos { x: min_x - 1, y: min_y });
+                }
+                ends.push(Pos { x: max_x + 1, y: min_y });
+            }
+
+            ends.retain(|p| p.x < self.view.height() && p.y < self.view.width() && unknown(p) && covered(p));
+            if !ends.is_empty() {
+                return ends;
+            }
+        }
+
+        let mut sides: Vec<Pos> = Vec::new();
+        for p in group.iter().flat_map(|p| self.view.sides(*p)).filter(unknown) {
+            if !sides.contains(&p) {
+                sides.push(p);
+            }
+        }
+        if sides.iter().any(covered) {
+            sides.retain(covered);
+        }
+        sides
+    }
+}
+
+impl Ai for HuntTargetAi {
+    fn next_shot(&mut self) -> Option<Pos> {
+        // target mode: finish the boats already hit
+        if let Some(&hit) = self.view.open_hits().first() {
+            let cells = self.target_cells(hit);
+            if !cells.is_empty() {
+                return pick(&mut self.rng, &cells);
+            }
+        }
+
+        // hunt mode: if the shortest boat afloat has length n only one cell out of n must be tried,
+        // a checkerboard is enough for boats of length 2 or more
+        let unknown = self.view.unknown_cells();
+        let shortest = self.view.afloat().iter().position(|&n| n > 0).map_or(1, |i| i + 1);
+        let parity: Vec<Pos> = unknown.iter().copied().filter(|p| (p.x + p.y) % 2 == 0).collect();
+
+        if shortest >= 2 && !parity.is_empty() {
+            pick(&mut self.rng, &parity)
+        } else {
+            pick(&mut self.rng, &unknown)
+        }
+    }
+
+    fn record(&mut self, pos: Pos, outcome: ShotOutcome) {
+        self.view.record(pos, outcome);
+    }
+
+    fn reveal(&mut self, boat: &[Pos]) {
+        self.view.reveal(boat);
+    }
+}
+
+//------------------------------------------------
+// Probability density
+//------------------------------------------------
+pub struct DensityAi {
+    view: TargetView,
+    rng: Rng,
+}
+
+impl Ai for DensityAi {
+    fn next_shot(&mut self) -> Option<Pos> {
+        let density = density(&self.view);
+        let best = self.view.unknown_cells().iter().map(|p| density[p.x][p.y]).max()?;
+
+        if best == 0 {
+            let unknown = self.view.unknown_cells();
+            return pick(&mut self.rng, &unknown);
+        }
+
+        let cells: Vec<Pos> = self.view.unknown_cells().into_iter().filter(|p| density[p.x][p.y] == best).collect();
+        pick(&mut self.rng, &cells)
+    }
+
+    fn record(&mut self, pos: Pos, outcome: ShotOutcome) {
+        self.view.record(pos, outcome);
+    }
+
+    fn reveal(&mut self, boat: &[Pos]) {
+        self.view.reveal(boat);
+    }
+}
+
+// For every unknown cell, the number of legal placements of the boats still afloat covering it
+// While some boat is hit but not sunk only the placements through the hits count, weighted by the hits they cover
+pub fn density(view: &TargetView) -> Vec<Vec<u64>> {
+    let mut density = vec![vec![0; view.width()]; view.height()];
+    let targeting = !view.open_hits().is_empty();
+
+    for (i, &count) in view.afloat().iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+
+        // a sunk boat only tells its size: every shape of that size may still be afloat
+        for shape in view.shapes(i + 1) {
+            for start in view.cells() {
+                let cells: Vec<Pos> = shape.iter().map(|o| Pos { x: start.x + o.x, y: start.y + o.y }).collect();
+                let inside = cells.iter().all(|p| p.x < view.height() && p.y < view.width());
+                if !inside || cells.iter().any(|p| matches!(view.get(*p), Mark::Miss | Mark::Sunk | Mark::Water)) {
+                    continue;
+                }
+
+                let hits = cells.iter().filter(|p| view.get(**p) == Mark::Hit).count() as u64;
+                let weight = match (targeting, hits) {
+                    (true, 0) => continue,
+                    (true, hits) => hits * count as u64,
+                    (false, _) => count as u64,
+                };
+
+                for p in cells.iter().filter(|p| view.get(**p) == Mark::Unknown) {
+                    density[p.x][p.y] += weight;
+                }
+            }
+        }
+    }
+
+    density
+}
+
+//------------------------------------------------
+// Simulation
+//------------------------------------------------
+pub struct Report {
+    pub games: usize,
+    pub average: f64,   // average number of shots to sink the whole fleet
+    pub best: usize,
+    pub worst: usize,
+}
+
+// Let the ai shoot at the board until all the boats are sunk, returns the number of shots
+pub fn play(ai: &mut dyn Ai, board: &mut Board) -> usize {
+    let mut shots = 0;
+
+    while !board.all_sunk() {
+        let pos = match ai.next_shot() {
+            Some(pos) => pos,
+            None => break,
+        };
+        let outcome = match board.fire(pos) {
+            Ok(outcome) => outcome,
+            Err(_) => break,
+        };
+        ai.record(pos, outcome);
+        if let (ShotOutcome::Sunk(_), true, Some(id)) = (outcome, board.rules().reveal, board.boat_id(pos)) {
+            ai.reveal(&board.boat_cells(id));
+        }
+        shots += 1;
+    }
+
+    shots
+}
+
+// Play games against random fleets, the same seed always gives the same boards and the same shots
+pub fn simulate(strategy: Strategy, width: usize, height: usize, fleet: &[u8], games: usize, seed: u64) -> Result<Report, Error> {
+    let mut seeds = Rng::new(seed);
+    let mut report = Report { games, average: 0.0, best: usize::MAX, worst: 0 };
+    let mut total = 0;
+
+    for _ in 0..games {
+        let mut board = Board::with_rules(width, height, fleet)?;
+        board.random_fleet(seeds.next_u64())?;
+        let mut ai = strategy.create(&board, seeds.next_u64());
+
+        let shots = play(ai.as_mut(), &mut board);
+        total += shots;
+        report.best = std::cmp::min(report.best, shots);
+        report.worst = std::cmp::max(report.worst, shots);
+    }
+
+    if games > 0 {
+        report.average = total as f64 / games as f64;
+    } else {
+        report.best = 0;
+    }
+
+    Ok(report)
+}
+
+/*
+ * Helper functions:
+ *
+ * pick chooses a random element of a slice
+ * min_max finds the smallest and the largest value of an iterator
+ */
+
+fn pick(rng: &mut Rng, cells: &[Pos]) -> Option<Pos> {
+    if cells.is_empty() {
+        None
+    } else {
+        Some(cells[rng.below(cells.len())])
+    }
+}
+
+fn min_max(values: impl Iterator<Item = usize>) -> (usize, usize) {
+    values.fold((usize::MAX, 0), |(min, max), v| (std::cmp::min(min, v), std::cmp::max(max, v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Boat;
+    use crate::piece::Piece;
+
+    // An L on its own, hit on three cells around its corner: no straight boat covers them, the L does
+    fn bent_hits() -> TargetView {
+        let mut board = Board::with_pieces(5, 5, &[], &[(Piece::standard('L').unwrap(), 1)]).unwrap();
+        board.add_boat(Boat::Piece('L', 0), Pos { x: 1, y: 1 }).unwrap();
+
+        let mut view = TargetView::for_board(&board);
+        for pos in [Pos { x: 2, y: 1 }, Pos { x: 3, y: 1 }, Pos { x: 3, y: 2 }] {
+            view.record(pos, board.fire(pos).unwrap());
+        }
+        view
+    }
+
+    #[test]
+    fn density_counts_pieces_in_every_rotation() {
+        let density = density(&bent_hits());
+
+        // the two rotations of the L through the three hits
+        assert!(density[1][1] > 0);
+        assert!(density[3][3] > 0);
+    }
+
+    #[test]
+    fn hunt_target_finishes_a_bent_piece() {
+        let view = bent_hits();
+        let ai = HuntTargetAi { view, rng: Rng::new(0) };
+
+        let cells = ai.target_cells(Pos { x: 2, y: 1 });
+        assert!(cells.contains(&Pos { x: 1, y: 1 }), "{:?}", cells);
+    }
+}
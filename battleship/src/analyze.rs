@@ -0,0 +1,235 @@
+/*
+ * Monte Carlo statistics on random fleets, to study where boats end up and where to shoot
+ * Many fleets are placed at random (as Board::random_fleet does) and every cell counts the fleets
+ * with a boat on it
+ *
+ * Given what is known of an opponent's board, only the fleets that agree with it are counted,
+ * which gives the probability of a boat in each cell after the shots fired so far:
+ * the known water is never used, every hit or sunk cell must hold a boat, a sunk boat must be
+ * made only of sunk cells and a boat made only of hit cells would have been sunk
+ * The sunk boats are known exactly, so they are placed before the random ones
+ * (if the rules let boats touch, a sunk cell only tells that its boat is sunk, made of hit cells,
+ * and no boat is placed in advance)
+ */
+
+use crate::board::{Board, Boat, Error, Pos};
+use crate::rng::Rng;
+use crate::target::{Mark, TargetView};
+
+pub struct Heatmap {
+    pub samples: usize,         // fleets placed
+    pub accepted: usize,        // fleets that agree with what is known
+    pub counts: Vec<Vec<u64>>,  // counts[x][y] = accepted fleets with a boat in (x, y)
+}
+
+impl Heatmap {
+
+    // Probability of a boat in each cell, 0 everywhere if no fleet was accepted
+    pub fn probabilities(&self) -> Vec<Vec<f64>> {
+        self.counts
+            .iter()
+            .map(|row| row.iter().map(|&n| if self.accepted == 0 { 0.0 } else { n as f64 / self.accepted as f64 }).collect())
+            .collect()
+    }
+
+    // One line per row, probabilities separated by commas
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for row in self.probabilities() {
+            let row: Vec<String> = row.iter().map(|p| format!("{:.4}", p)).collect();
+            csv += &row.join(",");
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+// How often each cell holds a boat when the fleet of rules is placed at random
+pub fn occupancy(rules: &Board, samples: usize, seed: u64) -> Result<Heatmap, Error> {
+    let view = TargetView::for_board(rules);
+    posterior(rules, &view, samples, seed)
+}
+
+// Probability of a boat in each cell given what a shooter knows of the opponent's board
+pub fn posterior(rules: &Board, view: &TargetView, samples: usize, seed: u64) -> Result<Heatmap, Error> {
+    let mut heatmap = Heatmap { samples, accepted: 0, counts: vec![vec![0; rules.width()]; rules.height()] };
+    let water: Vec<Vec<bool>> = (0..rules.height())
+        .map(|x| (0..rules.width()).map(|y| matches!(view.get(Pos { x, y }), Mark::Miss | Mark::Water)).collect())
+        .collect();
+    let mut seeds = Rng::new(seed);
+    let mut placed = 0;
+    let start = if rules.rules().touching { rules.cleared() } else { with_sunk_boats(rules, view) };
+
+    for _ in 0..samples {
+        let mut board = start.clone();
+        if board.random_fleet_avoiding(seeds.next_u64(), &water).is_err() {
+            continue;
+        }
+        placed += 1;
+        if !agrees(&board, view) {
+            continue;
+        }
+
+        heatmap.accepted += 1;
+        for (x, row) in heatmap.counts.iter_mut().enumerate() {
+            for (y, count) in row.iter_mut().enumerate() {
+                if board.boat_id(Pos { x, y }).is_some() {
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    // not a single fleet fits around the known water
+    if placed == 0 && samples > 0 {
+        return Err(Error::NoLayout);
+    }
+    Ok(heatmap)
+}
+
+// Empty board with the sunk boats of the view already placed (as far as they fit the fleet)
+fn with_sunk_boats(rules: &Board, view: &TargetView) -> Board {
+    let mut board = rules.cleared();
+    let mut seen: Vec<Pos> = Vec::new();
+
+    for start in view.cells().filter(|p| view.get(*p) == Mark::Sunk) {
+        if seen.contains(&start) {
+            continue;
+        }
+
+        // the sunk cells connected to start are one boat
+        let mut cells = vec![start];
+        let mut i = 0;
+        while i < cells.len() {
+            for n in view.sides(cells[i]) {
+                if view.get(n) == Mark::Sunk && !cells.contains(&n) {
+                    cells.push(n);
+                }
+            }
+            i += 1;
+        }
+        seen.extend(cells.iter().copied());
+
+        let top = cells.iter().map(|p| p.x).min().unwrap_or(0);
+        let left = cells.iter().map(|p| p.y).min().unwrap_or(0);
+        let corner = Pos { x: top, y: left };
+        let boat = if cells.iter().all(|p| p.x == top) {
+            Some(Boat::Horizontal(cells.len()))
+        } else if cells.iter().all(|p| p.y == left) {
+            Some(Boat::Vertical(cells.len()))
+        } else {
+            let offsets: Vec<Pos> = cells.iter().map(|p| Pos { x: p.x - top, y: p.y - left }).collect();
+            rules.pieces().iter().find_map(|piece| piece.rotation_of(&offsets).map(|r| Boat::Piece(piece.name(), r)))
+        };
+
+        // a boat that doesn't fit is left to the random placement, which will never agree with it
+        if let Some(boat) = boat {
+            let _ = board.add_boat(boat, corner);
+        }
+    }
+
+    board
+}
+
+// The boats of a fleet placed away from the known water agree with the hits and sunk boats of the view
+fn agrees(board: &Board, view: &TargetView) -> bool {
+    let mut boats: Vec<Vec<Mark>> = Vec::new();
+
+    for x in 0..board.height() {
+        for y in 0..board.width() {
+            let pos = Pos { x, y };
+            let mark = view.get(pos);
+            match board.boat_id(pos) {
+                Some(id) => {
+                    if boats.len() < id as usize {
+                        boats.resize(id as usize, Vec::new());
+                    }
+                    boats[id as usize - 1].push(mark);
+                }
+                None if matches!(mark, Mark::Hit | Mark::Sunk) => return false,
+                None => {}
+            }
+        }
+    }
+
+    // the sunk cells are the whole boat unless boats may touch and the sunk cells are not revealed
+    let exact = !board.rules().touching || board.rules().reveal;
+    boats.iter().filter(|marks| !marks.is_empty()).all(|marks| {
+        let sunk = marks.iter().filter(|&&m| m == Mark::Sunk).count();
+        let hit = marks.iter().filter(|&&m| m == Mark::Hit).count();
+        match (sunk, exact) {
+            (0, _) => hit < marks.len(),
+            (_, true) => sunk == marks.len(),
+            (_, false) => sunk + hit == marks.len(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::ShotOutcome;
+
+    // Board of one row with the given fleet, and an empty view of it
+    fn row(width: usize, fleet: &[u8]) -> (Board, TargetView) {
+        let board = Board::with_rules(width, 1, fleet).unwrap();
+        let view = TargetView::for_board(&board);
+        (board, view)
+    }
+
+    #[test]
+    fn a_boat_that_fills_the_board_is_everywhere() {
+        let (board, _) = row(2, &[0, 1]);
+        let heatmap = occupancy(&board, 50, 1).unwrap();
+
+        assert_eq!((heatmap.samples, heatmap.accepted), (50, 50));
+        assert_eq!(heatmap.probabilities(), vec![vec![1.0, 1.0]]);
+        assert_eq!(heatmap.to_csv(), "1.0000,1.0000\n");
+    }
+
+    #[test]
+    fn the_middle_of_three_cells_always_holds_a_boat_of_two() {
+        let (board, _) = row(3, &[0, 1]);
+        let heatmap = occupancy(&board, 200, 2).unwrap();
+
+        let counts = &heatmap.counts[0];
+        assert_eq!(counts[1], 200);
+        assert_eq!(counts[0] + counts[2], 200);
+        assert!(counts[0] > 0 && counts[2] > 0);
+    }
+
+    #[test]
+    fn the_only_cell_left_has_probability_one() {
+        let (board, mut view) = row(3, &[1]);
+        view.record(Pos { x: 0, y: 0 }, ShotOutcome::Miss);
+        view.record(Pos { x: 0, y: 2 }, ShotOutcome::Miss);
+
+        let heatmap = posterior(&board, &view, 20, 3).unwrap();
+        assert_eq!(heatmap.accepted, 20);
+        assert_eq!(heatmap.probabilities(), vec![vec![0.0, 1.0, 0.0]]);
+
+        // with every cell missed no fleet fits
+        view.record(Pos { x: 0, y: 1 }, ShotOutcome::Miss);
+        assert!(matches!(posterior(&board, &view, 20, 3), Err(Error::NoLayout)));
+    }
+
+    #[test]
+    fn hits_and_sunk_boats_fix_the_fleet() {
+        // a hit at the left end: the boat of two can only be on the first two cells
+        let (board, mut view) = row(4, &[0, 1]);
+        view.record(Pos { x: 0, y: 0 }, ShotOutcome::Hit);
+        let heatmap = posterior(&board, &view, 100, 4).unwrap();
+        assert!(heatmap.accepted > 0);
+        assert_eq!(heatmap.probabilities(), vec![vec![1.0, 1.0, 0.0, 0.0]]);
+
+        // the boat of two sunk on the first two cells: the boat of one is on one of the last two, away from it
+        let (board, mut view) = row(5, &[1, 1]);
+        view.record(Pos { x: 0, y: 0 }, ShotOutcome::Hit);
+        view.record(Pos { x: 0, y: 1 }, ShotOutcome::Sunk(2));
+        let heatmap = posterior(&board, &view, 100, 5).unwrap();
+        let counts = &heatmap.counts[0];
+        assert_eq!(heatmap.accepted, 100);
+        assert_eq!(&counts[..3], &[100, 100, 0]);
+        assert_eq!(counts[3] + counts[4], 100);
+    }
+}
@@ -0,0 +1,1217 @@
+/*
+ * Battleship game
+ * The game is played on a 20x20 board (other sizes can be chosen with Board::with_rules)
+ * The player has to place the boats of the fleet on the board
+ * The game ends when all the boats are sunk
+ * A boat can be represented as a vertical or horizontal line, or as a piece of another shape (see piece.rs)
+ * 
+ * Author: Stefano Roy Bisignano
+ */
+
+const BSIZE: usize = 20;            // default board size
+const MAX_SIZE: usize = 26;         // largest width or height accepted by Board::with_rules
+pub const MAX_BOATS: u8 = 26;       // boat ids are written in the board file as letters A..Z
+const RANDOM_ATTEMPTS: usize = 100_000;     // placements tried by random_fleet before giving up
+const BASELINE_FLEET: [u8; 4] = [4, 3, 2, 1];   // fleet of the boards saved as a plain grid of 0 and 1
+
+/*
+ * Board file format (version 5)
+ *
+ *   battleship v5 size=20x20 fleet=4,3,2,1 remaining=3,3,2,1 pieces=1 rules=salvo,reveal
+ *   piece L count=1 remaining=0 cells=0,0/1,0/2,0/2,1
+ *   A...................
+ *   a..*..C.............
+ *   ....BBBCC...........
+ *   ... height rows of width cells
+ *
+ * size is width x height, fleet is the quota given to Board::with_rules and remaining the boats
+ * still to place (element i is the number of boats of length i + 1)
+ * pieces is the number of piece lines that follow, one for each shape of the fleet that is not a straight line:
+ * its letter, how many of them are in the fleet and still to place, and its cells in rotation 0
+ * rules lists the variants in use (see Rules), or is classic when there are none
+ * '.' is water, '*' is water that has been shot (a miss)
+ * an uppercase letter is a cell of the boat with that id, the same letter in lowercase is a hit cell
+ * Older files are still accepted with the classic rules: version 4 has no rules, version 3 has no pieces
+ * either, version 2 has no size either (20x20, boats of length 1 to 4), version 1 has no shots either
+ *
+ * Boards saved before the header existed are a plain 20x20 grid, '1' for a boat and '0' for water:
+ * they are read with the fleet 4,3,2,1, the boats found on the grid being placed and the others still to place
+ */
+const FORMAT_VERSION: &str = "v5";
+const FORMAT_VERSION_NO_RULES: &str = "v4";
+const FORMAT_VERSION_NO_PIECES: &str = "v3";
+const FORMAT_VERSION_NO_SIZE: &str = "v2";
+const FORMAT_VERSION_NO_SHOTS: &str = "v1";
+const WATER: char = '.';
+const MISS: char = '*';
+
+use crate::piece::{Piece, ROTATIONS};
+use crate::rng::Rng;
+use crate::validate::{validate, Violation};
+
+// Data structures
+#[derive(Clone)]
+pub struct Board {  
+    width: usize,
+    height: usize,
+    fleet: Vec<u8>,                     // fleet[i] = boats of length i + 1 in the whole fleet
+    boats: Vec<u8>,                     // boats[i] = boats of length i + 1 still to place
+    data: Vec<Vec<u8>>,                 // 0 = water, otherwise the id of the boat in the cell
+    shots: Vec<Vec<bool>>,              // true if the cell has already been shot
+    pieces: Vec<Piece>,                 // shapes of the fleet that are not straight lines
+    piece_fleet: Vec<u8>,               // piece_fleet[i] = pieces shaped as pieces[i] in the whole fleet
+    pieces_left: Vec<u8>,               // pieces_left[i] = pieces shaped as pieces[i] still to place
+    rules: Rules,
+
+}
+
+// Rule variants, chosen when the board is created; the classic game has none of them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rules {
+    pub salvo: bool,        // every turn a player fires one shot for each of their boats still afloat
+    pub touching: bool,     // boats may touch each other (in the classic game not even diagonally)
+    pub reveal: bool,       // the cells of a sunk boat are shown to the shooter
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boat {         // A boat can be represented as a vertical or horizontal line
+    Vertical(usize),
+    Horizontal(usize),
+    Piece(char, usize),     // piece with the given letter, turned clockwise the given number of times
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pos{
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Overlap,      // Boat overlaps
+    OutOfBounds,  // Boat out of bounds
+    BoatCount,    // Number of boats exceeded (or invalid boat length)
+    Adjacent(Pos),  // Boat touches another boat in the given cell
+    Rules,        // Invalid board size or fleet
+    NoLayout,     // No legal placement found for the remaining fleet
+    UnknownBoat,  // No boat with the given id on the board
+    BoatHit,      // The boat has already been hit and can't be changed
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    Header,                 // Missing or malformed header line
+    Version(String),        // Unsupported file format version
+    Rules,                  // Invalid board size or fleet in the header
+    Dimensions,             // Wrong number of rows or columns
+    BadChar(Pos, char),     // Unexpected character in a cell
+    Shape(char),            // The cells of a boat are not a straight line nor a piece of the fleet
+    Quota,                  // Remaining quota does not match the boats on the grid
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShotOutcome {
+    Miss,           // Only water in the cell
+    Hit,            // A boat has been hit but it is still afloat
+    Sunk(usize),    // The last cell of a boat of the given length has been hit
+    AlreadyShot,    // The cell had already been shot, nothing changes
+}
+
+
+// Board structure implementation
+impl Board {
+
+    // 20x20 board, boats[i] is the number of boats of length i + 1
+    pub fn new(boats: &[u8]) -> Board {
+        Board::empty(BSIZE, BSIZE, boats)
+    }   
+
+    // width x height board with a custom fleet, e.g. a 10x10 board with a carrier: (10, 10, &[0, 1, 2, 1, 1])
+    pub fn with_rules(width: usize, height: usize, fleet: &[u8]) -> Result<Board, Error> {
+        Board::with_pieces(width, height, fleet, &[])
+    }
+
+    // Like with_rules, with pieces in the fleet too: each piece comes with how many of them there are
+    pub fn with_pieces(width: usize, height: usize, fleet: &[u8], pieces: &[(Piece, u8)]) -> Result<Board, Error> {
+        if !valid_rules(width, height, fleet, pieces) {
+            return Err(Error::Rules);
+        }
+
+        let mut board = Board::empty(width, height, fleet);
+        board.pieces = pieces.iter().map(|(piece, _)| piece.clone()).collect();
+        board.piece_fleet = pieces.iter().map(|(_, count)| *count).collect();
+        board.pieces_left = board.piece_fleet.clone();
+        Ok(board)
+    }
+
+    pub fn add_boat(&mut self, boat: Boat, pos: Pos) -> Result<(), Error> {
+        let id = self.free_id().ok_or(Error::BoatCount)?;
+        self.add_boat_with_id(boat, pos, id)
+    }
+
+    // Take a boat off the board, it goes back to the boats still to place
+    // Returns the boat and its top left cell, so that it can be placed again
+    pub fn remove_boat(&mut self, id: u8) -> Result<(Boat, Pos), Error> {
+        let (boat, pos) = self.boat_placement(id)?;
+        self.take_boat(id, boat);
+
+        Ok((boat, pos))
+    }
+
+    // Move a boat so that its top left corner is in pos, keeping its direction and its id
+    // If the new position is not valid the board is left as it was
+    pub fn move_boat(&mut self, id: u8, pos: Pos) -> Result<(), Error> {
+        let backup = self.clone();
+
+        let (boat, _) = self.remove_boat(id)?;
+        self.add_boat_with_id(boat, pos, id).inspect_err(|_| *self = backup)
+    }
+
+    // Turn a boat from vertical to horizontal or the other way round, around its top left cell
+    // (a piece is turned a quarter clockwise around the top left corner of its rectangle)
+    // If the rotated boat is not valid the board is left as it was
+    pub fn rotate_boat(&mut self, id: u8) -> Result<(), Error> {
+        let backup = self.clone();
+
+        let rotated = match self.remove_boat(id)? {
+            (Boat::Vertical(length), pos) => (Boat::Horizontal(length), pos),
+            (Boat::Horizontal(length), pos) => (Boat::Vertical(length), pos),
+            (Boat::Piece(name, rotation), pos) => (Boat::Piece(name, (rotation + 1) % ROTATIONS), pos),
+        };
+        self.add_boat_with_id(rotated.0, rotated.1, id).inspect_err(|_| *self = backup)
+    }
+
+    fn add_boat_with_id(&mut self, boat: Boat, pos: Pos, id: u8) -> Result<(), Error> {
+        // being self in the function is needed to create a new board with let mut 
+        let new_board = self;
+
+        // Check the quota for this boat length (or piece) before touching the board
+        let left = match boat {
+            Boat::Vertical(length) | Boat::Horizontal(length) => {
+                length.checked_sub(1).and_then(|i| new_board.boats.get(i)).copied()
+            }
+            Boat::Piece(name, _) => new_board.piece_index(name).map(|i| new_board.pieces_left[i]),
+        };
+        if left.unwrap_or(0) == 0 {
+            return Err(Error::BoatCount);
+        }
+
+        // Switch case to check the type of boat (vertical, horizontal or piece)
+        let offsets: Vec<Pos> = match boat {
+            
+            // Vertical boat case: a column of length cells
+            Boat::Vertical(length) => (0..length).map(|i| Pos { x: i, y: 0 }).collect(),
+
+            // Horizontal boat case: a row of length cells
+            Boat::Horizontal(length) => (0..length).map(|i| Pos { x: 0, y: i }).collect(),
+
+            // Piece case: the cells of the piece in the given rotation
+            Boat::Piece(name, rotation) => match new_board.piece_index(name) {
+                Some(i) => new_board.pieces[i].cells(rotation),
+                None => return Err(Error::BoatCount),
+            },
+        };
+        new_board.add_cells(&offsets, pos, id)?;
+
+        // The boat has been placed: one less of this kind to place
+        match boat {
+            Boat::Vertical(length) | Boat::Horizontal(length) => new_board.boats[length - 1] -= 1,
+            Boat::Piece(name, _) => {
+                if let Some(i) = new_board.piece_index(name) {
+                    new_board.pieces_left[i] -= 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Place all the boats still to place at random positions, the same seed always gives the same board
+    pub fn random_fleet(&mut self, seed: u64) -> Result<(), Error> {
+        self.random_fleet_avoiding(seed, &[])
+    }
+
+    // Like random_fleet, leaving as water the cells that are true in water (missing rows or columns are free)
+    pub fn random_fleet_avoiding(&mut self, seed: u64, water: &[Vec<bool>]) -> Result<(), Error> {
+        // pieces and then the longest boats first: they are the hardest to fit
+        let mut boats = Vec::new();
+        for (piece, &count) in self.pieces.iter().zip(self.pieces_left.iter()) {
+            boats.extend(std::iter::repeat_n(Boat::Piece(piece.name(), 0), count as usize));
+        }
+        for (i, &count) in self.boats.iter().enumerate().rev() {
+            boats.extend(std::iter::repeat_n(Boat::Horizontal(i + 1), count as usize));
+        }
+
+        // a fleet that can't fit is refused at once instead of trying every placement
+        if !self.has_room(&boats, water) {
+            return Err(Error::NoLayout);
+        }
+
+        let mut rng = Rng::new(seed);
+        let mut attempts = RANDOM_ATTEMPTS;
+        let mut board = self.clone();
+        if !place_randomly(&mut board, &boats, water, &mut rng, &mut attempts) {
+            return Err(Error::NoLayout);
+        }
+        *self = board;
+
+        Ok(())
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Whole fleet: element i is the number of boats of length i + 1
+    pub fn fleet(&self) -> &[u8] {
+        &self.fleet
+    }
+
+    // Boats still to place: element i is the number of boats of length i + 1
+    pub fn remaining_boats(&self) -> &[u8] {
+        &self.boats
+    }
+
+    // Shapes of the fleet that are not straight lines
+    pub fn pieces(&self) -> &[Piece] {
+        &self.pieces
+    }
+
+    // Element i is the number of pieces shaped as pieces()[i] in the whole fleet
+    pub fn piece_fleet(&self) -> &[u8] {
+        &self.piece_fleet
+    }
+
+    // Element i is the number of pieces shaped as pieces()[i] still to place
+    pub fn remaining_pieces(&self) -> &[u8] {
+        &self.pieces_left
+    }
+
+    // True when every boat and every piece of the fleet is on the board
+    pub fn is_complete(&self) -> bool {
+        self.boats.iter().chain(self.pieces_left.iter()).all(|&n| n == 0)
+    }
+
+    // Whole fleet counted by number of cells, pieces included: element i is the number of boats of i + 1 cells
+    // (this is what a shooter can tell apart, since a sunk boat only reveals its size)
+    pub fn sizes(&self) -> Vec<u8> {
+        let largest = self.pieces.iter().map(|p| p.len()).max().unwrap_or(0);
+        let mut sizes = self.fleet.clone();
+        if sizes.len() < largest {
+            sizes.resize(largest, 0);
+        }
+        for (piece, &count) in self.pieces.iter().zip(self.piece_fleet.iter()) {
+            sizes[piece.len() - 1] += count;
+        }
+        sizes
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    // Choose the rule variants, only while the board is still empty
+    pub fn set_rules(&mut self, rules: Rules) -> Result<(), Error> {
+        if self.data.iter().flatten().any(|&cell| cell != 0) {
+            return Err(Error::Rules);
+        }
+        self.rules = rules;
+        Ok(())
+    }
+
+    // Empty board with the same size, fleet and rules
+    pub fn cleared(&self) -> Board {
+        let mut board = Board::empty(self.width, self.height, &self.fleet);
+        board.pieces = self.pieces.clone();
+        board.piece_fleet = self.piece_fleet.clone();
+        board.pieces_left = self.piece_fleet.clone();
+        board.rules = self.rules;
+        board
+    }
+
+    // Kind and top left corner of the boat with the given id, None if there is no such boat
+    pub fn placement(&self, id: u8) -> Option<(Boat, Pos)> {
+        let cells = self.cells_of(id);
+        let top = cells.iter().map(|p| p.x).min()?;
+        let left = cells.iter().map(|p| p.y).min()?;
+        if let Some(boat) = straight(&cells) {
+            return Some((boat, cells[0]));
+        }
+
+        self.pieces
+            .iter()
+            .find_map(|piece| piece.rotation_of(&cells).map(|r| Boat::Piece(piece.name(), r)))
+            .map(|boat| (boat, Pos { x: top, y: left }))
+    }
+
+    // Id of the boat occupying a cell, None for water
+    pub fn boat_id(&self, pos: Pos) -> Option<u8> {
+        match self.data[pos.x][pos.y] {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    // True if the cell has already been shot
+    pub fn is_shot(&self, pos: Pos) -> bool {
+        self.shots[pos.x][pos.y]
+    }
+
+    // Shoot at a cell and tell what has been hit
+    pub fn fire(&mut self, pos: Pos) -> Result<ShotOutcome, Error> {
+        if !self.contains(pos) {
+            return Err(Error::OutOfBounds);
+        }
+        if self.shots[pos.x][pos.y] {
+            return Ok(ShotOutcome::AlreadyShot);
+        }
+        self.shots[pos.x][pos.y] = true;
+
+        let id = match self.boat_id(pos) {
+            Some(id) => id,
+            None => return Ok(ShotOutcome::Miss),
+        };
+
+        if self.is_sunk(id) {
+            Ok(ShotOutcome::Sunk(self.cells_of(id).len()))
+        } else {
+            Ok(ShotOutcome::Hit)
+        }
+    }
+
+    // Cells of the boat with the given id, sorted by row then column (empty if there is no such boat)
+    pub fn boat_cells(&self, id: u8) -> Vec<Pos> {
+        self.cells_of(id)
+    }
+
+    // Number of boats on the board not sunk yet
+    pub fn boats_afloat(&self) -> usize {
+        (1..=MAX_BOATS).filter(|&id| !self.cells_of(id).is_empty() && !self.is_sunk(id)).count()
+    }
+
+    // True if all the cells of the boat with the given id have been hit
+    pub fn is_sunk(&self, id: u8) -> bool {
+        self.cells_of(id).iter().all(|p| self.shots[p.x][p.y])
+    }
+
+    // The game is over when every cell of every boat has been hit
+    pub fn all_sunk(&self) -> bool {
+        self.cells().all(|p| self.data[p.x][p.y] == 0 || self.shots[p.x][p.y])
+    }
+
+    /*
+     * Helper functions:
+     * 
+     * empty creates a board without checking the rules
+     * contains checks if a position is inside the board
+     * is_occupied checks if a cell is occupied or not
+     * cells iterates over all the positions of the board
+     * cells_of finds the cells of the boat with a given id
+     * boat_placement finds the kind and the top left corner of a boat that can still be changed
+     * take_boat clears the cells of a boat and puts it back with the boats to place
+     * piece_index finds a piece of the fleet by its letter
+     * free_id finds the lowest boat id not used on the board
+     * place_boat places a boat on the board
+     * check_adjacent checks that no boat touches the cells around a new boat
+     * add_cells adds a boat of any shape to the board
+     * from_grid reads a board saved as a plain grid of 0 and 1
+     * has_room tells if there are enough free cells for some boats, a quick check before placing them
+     */
+
+    fn empty(width: usize, height: usize, fleet: &[u8]) -> Board {
+        Board {
+            width,
+            height,
+            fleet: fleet.to_vec(),
+            boats: fleet.to_vec(),
+            data: vec![vec![0; width]; height],
+            shots: vec![vec![false; width]; height],
+            pieces: Vec::new(),
+            piece_fleet: Vec::new(),
+            pieces_left: Vec::new(),
+            rules: Rules::default(),
+        }
+    }
+
+    fn contains(&self, pos: Pos) -> bool {
+        pos.x < self.height && pos.y < self.width
+    }
+
+    fn is_occupied(&self, pos: Pos) -> bool {
+        self.data[pos.x][pos.y] != 0
+    }
+
+    fn cells(&self) -> impl Iterator<Item = Pos> {
+        let width = self.width;
+        (0..self.height).flat_map(move |x| (0..width).map(move |y| Pos { x, y }))
+    }
+
+    fn cells_of(&self, id: u8) -> Vec<Pos> {
+        self.cells().filter(|p| self.data[p.x][p.y] == id).collect()
+    }
+
+    fn boat_placement(&self, id: u8) -> Result<(Boat, Pos), Error> {
+        let placement = self.placement(id).ok_or(Error::UnknownBoat)?;
+        if self.cells_of(id).iter().any(|p| self.shots[p.x][p.y]) {
+            return Err(Error::BoatHit);
+        }
+
+        Ok(placement)
+    }
+
+    fn take_boat(&mut self, id: u8, boat: Boat) {
+        for p in self.cells_of(id) {
+            self.data[p.x][p.y] = 0;
+        }
+        match boat {
+            Boat::Vertical(length) | Boat::Horizontal(length) => self.boats[length - 1] += 1,
+            Boat::Piece(name, _) => {
+                if let Some(i) = self.piece_index(name) {
+                    self.pieces_left[i] += 1;
+                }
+            }
+        }
+    }
+
+    fn piece_index(&self, name: char) -> Option<usize> {
+        self.pieces.iter().position(|p| p.name() == name)
+    }
+
+    fn free_id(&self) -> Option<u8> {
+        let mut used = [false; MAX_BOATS as usize + 1];
+        for &id in self.data.iter().flatten() {
+            used[id as usize] = true;
+        }
+        (1..=MAX_BOATS).find(|&id| !used[id as usize])
+    }
+
+    fn place_boat(&mut self, pos: Pos, id: u8) {
+        self.data[pos.x][pos.y] = id;
+    }
+
+    // Scan the ring of 8 cells around every cell of the new boat, clipped to the board:
+    // after the overlap check any occupied cell here is a neighbour
+    fn check_adjacent(&self, cells: &[Pos]) -> Result<(), Error> {
+        for p in cells.iter() {
+            let x_end = std::cmp::min(p.x + 2, self.height);
+            let y_end = std::cmp::min(p.y + 2, self.width);
+
+            for x in p.x.saturating_sub(1)..x_end {
+                for y in p.y.saturating_sub(1)..y_end {
+                    if self.is_occupied(Pos { x, y }) {
+                        return Err(Error::Adjacent(Pos { x, y }));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // offsets are the cells of the boat relative to pos, its top left corner
+    fn add_cells(&mut self, offsets: &[Pos], pos: Pos, id: u8) -> Result<(), Error> {
+        let cells: Vec<Pos> = offsets.iter().map(|o| Pos { x: pos.x + o.x, y: pos.y + o.y }).collect();
+
+        if cells.iter().any(|p| !self.contains(*p)) {
+            return Err(Error::OutOfBounds);
+        }
+
+        if cells.iter().any(|p| self.is_occupied(*p)) {
+            return Err(Error::Overlap);
+        }
+
+        if !self.rules.touching {
+            self.check_adjacent(&cells)?;
+        }
+
+        for p in cells {
+            self.place_boat(p, id);
+        }
+
+        Ok(())
+    }
+
+    // Without the touching rule a boat grown by one row below and one column to the right covers cells
+    // that no other grown boat covers, all inside a board one row and one column larger
+    fn has_room(&self, boats: &[Boat], water: &[Vec<bool>]) -> bool {
+        let cells = |boat: &Boat, rotation: usize| match *boat {
+            Boat::Vertical(length) | Boat::Horizontal(length) => (0..length).map(|y| Pos { x: 0, y }).collect(),
+            Boat::Piece(name, _) => self.piece_index(name).map_or(Vec::new(), |i| self.pieces[i].cells(rotation)),
+        };
+
+        let free = self.cells().filter(|p| !self.is_occupied(*p) && !is_set(water, *p)).count();
+        let needed: usize = boats.iter().map(|boat| cells(boat, 0).len()).sum();
+        if needed > free {
+            return false;
+        }
+        if self.rules.touching {
+            return true;
+        }
+
+        let grown: usize = boats
+            .iter()
+            .map(|boat| {
+                (0..ROTATIONS)
+                    .map(|rotation| {
+                        let mut covered: Vec<Pos> = cells(boat, rotation)
+                            .iter()
+                            .flat_map(|p| [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(dx, dy)| Pos { x: p.x + dx, y: p.y + dy }))
+                            .collect();
+                        covered.sort_by_key(|p| (p.x, p.y));
+                        covered.dedup();
+                        covered.len()
+                    })
+                    .min()
+                    .unwrap_or(0)
+            })
+            .sum();
+        let placed = self.cells().filter(|p| self.is_occupied(*p)).count();
+
+        grown <= (self.width + 1) * (self.height + 1) - placed
+    }
+
+    // The grid has no ids: the occupied cells are split into straight boats as validate does
+    fn from_grid(s: &str) -> Result<Board, ParseError> {
+        let rows: Vec<&str> = s.lines().collect();
+        if rows.len() != BSIZE {
+            return Err(ParseError::Dimensions);
+        }
+
+        let mut occupied = Vec::new();
+        for (x, line) in rows.iter().enumerate() {
+            if line.chars().count() != BSIZE {
+                return Err(ParseError::Dimensions);
+            }
+            let row = line
+                .chars()
+                .enumerate()
+                .map(|(y, c)| match c {
+                    '0' => Ok(false),
+                    '1' => Ok(true),
+                    _ => Err(ParseError::BadChar(Pos { x, y }, c)),
+                })
+                .collect::<Result<Vec<bool>, ParseError>>()?;
+            occupied.push(row);
+        }
+
+        // these boards never checked for touching boats
+        let report = validate(&occupied, &BASELINE_FLEET, &[]);
+        let mut board = Board::new(&BASELINE_FLEET);
+        board.rules.touching = report.violations.iter().any(|v| matches!(v, Violation::Touching(..)));
+
+        for (boat, pos) in report.boats {
+            board.add_boat(boat, pos).map_err(|_| ParseError::Quota)?;
+        }
+
+        Ok(board)
+    }
+
+
+    // from string to board, see the file format description at the top
+    pub fn from(s: String) -> Result<Board, ParseError> {
+        if s.lines().next().is_some_and(|l| !l.is_empty() && l.chars().all(|c| c == '0' || c == '1')) {
+            return Board::from_grid(&s);
+        }
+
+        let mut lines = s.lines();
+        let header = parse_header(lines.next().ok_or(ParseError::Header)?)?;
+        let with_shots = header.version != FORMAT_VERSION_NO_SHOTS;
+
+        let mut pieces = Vec::new();
+        let mut pieces_left = Vec::new();
+        for _ in 0..header.pieces {
+            let (piece, count, remaining) = parse_piece(lines.next().ok_or(ParseError::Header)?)?;
+            pieces.push((piece, count));
+            pieces_left.push(remaining);
+        }
+
+        if !valid_rules(header.width, header.height, &header.fleet, &pieces)
+            || header.remaining.len() != header.fleet.len()
+            || pieces.iter().zip(pieces_left.iter()).any(|((_, count), left)| left > count)
+        {
+            return Err(ParseError::Rules);
+        }
+        let mut board = Board::with_pieces(header.width, header.height, &header.fleet, &pieces).map_err(|_| ParseError::Rules)?;
+        board.boats = header.remaining;
+        board.pieces_left = pieces_left;
+        board.rules = header.rules;
+
+        let rows: Vec<&str> = lines.collect();
+        if rows.len() != board.height {
+            return Err(ParseError::Dimensions);
+        }
+
+        for (i, line) in rows.iter().enumerate() {
+            if line.chars().count() != board.width {
+                return Err(ParseError::Dimensions);
+            }
+            for (j, c) in line.chars().enumerate() {
+                let (cell, shot) = match c {
+                    WATER => (0, false),
+                    'A'..='Z' => (c as u8 - b'A' + 1, false),
+                    MISS if with_shots => (0, true),
+                    'a'..='z' if with_shots => (c as u8 - b'a' + 1, true),
+                    _ => return Err(ParseError::BadChar(Pos { x: i, y: j }, c)),
+                };
+                board.data[i][j] = cell;
+                board.shots[i][j] = shot;
+            }
+        }
+
+        // Every boat on the grid plus the boats still to place must give back the fleet
+        let mut placed = vec![0; board.fleet.len()];
+        let mut placed_pieces = vec![0; board.pieces.len()];
+        for id in 1..=MAX_BOATS {
+            if board.cells_of(id).is_empty() {
+                continue;
+            }
+            match board.placement(id) {
+                Some((Boat::Vertical(length) | Boat::Horizontal(length), _)) => {
+                    if length > placed.len() {
+                        return Err(ParseError::Quota);
+                    }
+                    placed[length - 1] += 1;
+                }
+                Some((Boat::Piece(name, _), _)) => placed_pieces[board.piece_index(name).unwrap_or(0)] += 1,
+                None => return Err(ParseError::Shape((b'A' + id - 1) as char)),
+            }
+        }
+        let mismatch = (0..placed.len())
+            .any(|i| placed[i] + board.boats[i] as usize != board.fleet[i] as usize)
+            || (0..placed_pieces.len())
+                .any(|i| placed_pieces[i] + board.pieces_left[i] as usize != board.piece_fleet[i] as usize);
+        if mismatch {
+            return Err(ParseError::Quota);
+        }
+
+        Ok(board)
+    }
+    
+}
+
+// Backtracking search: try the placements of the first boat in random order and recurse on the others,
+// going back to the previous boat when a layout dead-ends; the board is left as it was if it fails
+fn place_randomly(board: &mut Board, boats: &[Boat], water: &[Vec<bool>], rng: &mut Rng, attempts: &mut usize) -> bool {
+    let (&boat, others) = match boats.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+
+    let mut candidates: Vec<(Boat, Pos)> = Vec::new();
+    for pos in board.cells() {
+        match boat {
+            Boat::Vertical(length) | Boat::Horizontal(length) => {
+                candidates.push((Boat::Horizontal(length), pos));
+                // a boat of length 1 is the same in both directions
+                if length > 1 {
+                    candidates.push((Boat::Vertical(length), pos));
+                }
+            }
+            Boat::Piece(name, _) => {
+                candidates.extend((0..ROTATIONS).map(|rotation| (Boat::Piece(name, rotation), pos)));
+            }
+        }
+    }
+    rng.shuffle(&mut candidates);
+
+    for (boat, pos) in candidates {
+        if *attempts == 0 {
+            return false;
+        }
+        *attempts -= 1;
+
+        let id = match board.free_id() {
+            Some(id) => id,
+            None => return false,
+        };
+        if board.add_boat_with_id(boat, pos, id).is_err() {
+            continue;
+        }
+        let dry = water.is_empty() || !board.cells_of(id).iter().any(|p| is_set(water, *p));
+        if dry && place_randomly(board, others, water, rng, attempts) {
+            return true;
+        }
+        board.take_boat(id, boat);
+    }
+
+    false
+}
+
+// The boat made of the given cells (sorted by row then column) if they are a straight line:
+// all in the same row with consecutive columns, or all in the same column with consecutive rows
+pub(crate) fn straight(cells: &[Pos]) -> Option<Boat> {
+    let first = *cells.first()?;
+
+    if cells.iter().enumerate().all(|(i, p)| p.x == first.x && p.y == first.y + i) {
+        Some(Boat::Horizontal(cells.len()))
+    } else if cells.iter().enumerate().all(|(i, p)| p.y == first.y && p.x == first.x + i) {
+        Some(Boat::Vertical(cells.len()))
+    } else {
+        None
+    }
+}
+
+fn is_set(cells: &[Vec<bool>], pos: Pos) -> bool {
+    cells.get(pos.x).and_then(|row| row.get(pos.y)).copied().unwrap_or(false)
+}
+
+// The board must fit in the file format and every boat of the fleet must fit in the board
+// Pieces must have different letters and different shapes (a rotated piece is the same piece)
+fn valid_rules(width: usize, height: usize, fleet: &[u8], pieces: &[(Piece, u8)]) -> bool {
+    let longest = fleet.iter().rposition(|&n| n > 0).map_or(0, |i| i + 1);
+    let total: usize = fleet.iter().chain(pieces.iter().map(|(_, n)| n)).map(|&n| n as usize).sum();
+
+    let fits = pieces.iter().all(|(piece, _)| {
+        (0..ROTATIONS).any(|r| {
+            let (rows, cols) = piece.size(r);
+            rows <= height && cols <= width
+        })
+    });
+    let distinct = pieces.iter().enumerate().all(|(i, (a, _))| {
+        pieces[..i].iter().all(|(b, _)| a.name() != b.name() && !a.same_shape(b))
+    });
+
+    (1..=MAX_SIZE).contains(&width)
+        && (1..=MAX_SIZE).contains(&height)
+        && longest <= std::cmp::max(width, height)
+        && total <= MAX_BOATS as usize
+        && fits
+        && distinct
+}
+
+pub(crate) struct Header<'a> {
+    pub version: &'a str,
+    pub width: usize,
+    pub height: usize,
+    pub fleet: Vec<u8>,
+    pub remaining: Vec<u8>,
+    pub pieces: usize,          // number of piece lines following the header
+    pub rules: Rules,
+}
+
+// Header line: "battleship v5 size=20x20 fleet=4,3,2,1 remaining=3,3,2,1 pieces=0 rules=classic"
+pub(crate) fn parse_header(line: &str) -> Result<Header<'_>, ParseError> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    match fields.as_slice() {
+        ["battleship", version @ FORMAT_VERSION, size, fleet, remaining, pieces, rules] => {
+            let pieces = pieces.strip_prefix("pieces=").ok_or(ParseError::Header)?;
+            let rules = rules.strip_prefix("rules=").ok_or(ParseError::Header)?;
+            Ok(Header {
+                pieces: pieces.parse().map_err(|_| ParseError::Header)?,
+                rules: rules.parse().map_err(|_| ParseError::Header)?,
+                ..parse_sized_header(version, size, fleet, remaining)?
+            })
+        }
+        ["battleship", version @ FORMAT_VERSION_NO_RULES, size, fleet, remaining, pieces] => {
+            let pieces = pieces.strip_prefix("pieces=").ok_or(ParseError::Header)?;
+            Ok(Header {
+                pieces: pieces.parse().map_err(|_| ParseError::Header)?,
+                ..parse_sized_header(version, size, fleet, remaining)?
+            })
+        }
+        ["battleship", version @ FORMAT_VERSION_NO_PIECES, size, fleet, remaining] => {
+            parse_sized_header(version, size, fleet, remaining)
+        }
+        ["battleship", version @ (FORMAT_VERSION_NO_SIZE | FORMAT_VERSION_NO_SHOTS), fleet, remaining] => {
+            let fleet = parse_counts(fleet.strip_prefix("fleet=").ok_or(ParseError::Header)?)?;
+            let remaining = parse_counts(remaining.strip_prefix("remaining=").ok_or(ParseError::Header)?)?;
+            if fleet.len() != 4 {
+                return Err(ParseError::Header);
+            }
+            Ok(Header { version, width: BSIZE, height: BSIZE, fleet, remaining, pieces: 0, rules: Rules::default() })
+        }
+        ["battleship", version, ..] => Err(ParseError::Version(version.to_string())),
+        _ => Err(ParseError::Header),
+    }
+}
+
+// Fields of the header from the size on, without pieces
+fn parse_sized_header<'a>(version: &'a str, size: &str, fleet: &str, remaining: &str) -> Result<Header<'a>, ParseError> {
+    let (width, height) = size
+        .strip_prefix("size=")
+        .and_then(|s| s.split_once('x'))
+        .ok_or(ParseError::Header)?;
+    Ok(Header {
+        version,
+        width: width.parse().map_err(|_| ParseError::Header)?,
+        height: height.parse().map_err(|_| ParseError::Header)?,
+        fleet: parse_counts(fleet.strip_prefix("fleet=").ok_or(ParseError::Header)?)?,
+        remaining: parse_counts(remaining.strip_prefix("remaining=").ok_or(ParseError::Header)?)?,
+        pieces: 0,
+        rules: Rules::default(),
+    })
+}
+
+// Piece line: "piece L count=1 remaining=0 cells=0,0/1,0/2,0/2,1", gives the piece, its count and how many are left
+pub(crate) fn parse_piece(line: &str) -> Result<(Piece, u8, u8), ParseError> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    let (name, count, remaining, cells) = match fields.as_slice() {
+        ["piece", name, count, remaining, cells] => (name, count, remaining, cells),
+        _ => return Err(ParseError::Header),
+    };
+    let mut name = name.chars();
+    let name = match (name.next(), name.next()) {
+        (Some(c), None) => c,
+        _ => return Err(ParseError::Header),
+    };
+    let count = count.strip_prefix("count=").and_then(|n| n.parse().ok()).ok_or(ParseError::Header)?;
+    let remaining = remaining.strip_prefix("remaining=").and_then(|n| n.parse().ok()).ok_or(ParseError::Header)?;
+    let cells = parse_offsets(cells.strip_prefix("cells=").ok_or(ParseError::Header)?).ok_or(ParseError::Header)?;
+
+    let piece = Piece::new(name, &cells).ok_or(ParseError::Rules)?;
+    Ok((piece, count, remaining))
+}
+
+// "0,0/1,0/2,0/2,1" -> the cells of a piece
+pub fn parse_offsets(s: &str) -> Option<Vec<Pos>> {
+    s.split('/')
+        .map(|cell| {
+            let (x, y) = cell.split_once(',')?;
+            Some(Pos { x: x.parse().ok()?, y: y.parse().ok()? })
+        })
+        .collect()
+}
+
+fn parse_counts(s: &str) -> Result<Vec<u8>, ParseError> {
+    s.split(',')
+        .map(|n| n.parse().map_err(|_| ParseError::Header))
+        .collect()
+}
+
+// The cells of a piece -> "0,0/1,0/2,0/2,1"
+pub fn format_offsets(cells: &[Pos]) -> String {
+    let cells: Vec<String> = cells.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+    cells.join("/")
+}
+
+fn format_counts(counts: &[u8]) -> String {
+    let counts: Vec<String> = counts.iter().map(|n| n.to_string()).collect();
+    counts.join(",")
+}
+
+// from board to string
+impl std::fmt::Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "battleship {} size={}x{} fleet={} remaining={} pieces={} rules={}",
+            FORMAT_VERSION,
+            self.width,
+            self.height,
+            format_counts(&self.fleet),
+            format_counts(&self.boats),
+            self.pieces.len(),
+            self.rules
+        )?;
+        for ((piece, count), left) in self.pieces.iter().zip(self.piece_fleet.iter()).zip(self.pieces_left.iter()) {
+            writeln!(
+                f,
+                "piece {} count={} remaining={} cells={}",
+                piece.name(),
+                count,
+                left,
+                format_offsets(&piece.cells(0))
+            )?;
+        }
+
+        for (row, shots) in self.data.iter().zip(self.shots.iter()) {
+            for (cell, shot) in row.iter().zip(shots.iter())   {
+                match (cell, shot) {
+                    (0, false) => write!(f, "{}", WATER)?,
+                    (0, true) => write!(f, "{}", MISS)?,
+                    (id, false) => write!(f, "{}", (b'A' + id - 1) as char)?,
+                    (id, true) => write!(f, "{}", (b'a' + id - 1) as char)?,
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+// "salvo,reveal" -> salvo and reveal, "classic" -> no variant
+impl std::str::FromStr for Rules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rules, String> {
+        let mut rules = Rules::default();
+        if s == "classic" {
+            return Ok(rules);
+        }
+
+        for variant in s.split(',') {
+            match variant {
+                "salvo" => rules.salvo = true,
+                "touching" => rules.touching = true,
+                "reveal" => rules.reveal = true,
+                _ => return Err(format!("unknown rule '{}', expected salvo, touching or reveal", variant)),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl std::fmt::Display for Rules {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let variants: Vec<&str> = [(self.salvo, "salvo"), (self.touching, "touching"), (self.reveal, "reveal")]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect();
+
+        if variants.is_empty() {
+            write!(f, "classic")
+        } else {
+            write!(f, "{}", variants.join(","))
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Overlap => write!(f, "boat overlap"),
+            Error::OutOfBounds => write!(f, "boat out of bounds"),
+            Error::BoatCount => write!(f, "too many boats"),
+            Error::Adjacent(p) => write!(f, "boat touches another boat at ({}, {})", p.x, p.y),
+            Error::Rules => write!(
+                f,
+                "invalid rules: the board must be at most {}x{}, with at most {} boats that fit in it",
+                MAX_SIZE, MAX_SIZE, MAX_BOATS
+            ),
+            Error::NoLayout => write!(f, "no room on the board for the remaining boats"),
+            Error::UnknownBoat => write!(f, "no boat with this id on the board"),
+            Error::BoatHit => write!(f, "the boat has already been hit"),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::Header => write!(f, "missing or malformed header line"),
+            ParseError::Version(v) => write!(f, "unsupported board file version {}", v),
+            ParseError::Rules => write!(f, "invalid board size or fleet in the header"),
+            ParseError::Dimensions => write!(f, "the grid does not match the board size in the header"),
+            ParseError::BadChar(p, c) => write!(f, "unexpected character '{}' at ({}, {})", c, p.x, p.y),
+            ParseError::Shape(id) => write!(f, "boat {} is not a straight line nor a piece of the fleet", id),
+            ParseError::Quota => write!(f, "the remaining boats do not match the boats on the board"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scattered_cells_are_not_a_straight_boat() {
+        let file = "battleship v5 size=6x3 fleet=0,0,1 remaining=0,0,0 pieces=0 rules=touching\nA.....\n.....A\nA.....\n";
+        assert_eq!(Board::from(file.to_string()).err(), Some(ParseError::Shape('A')));
+
+        let file = "battleship v5 size=6x3 fleet=0,0,1 remaining=0,0,0 pieces=0 rules=touching\nA.....\nA.....\nA.....\n";
+        let board = Board::from(file.to_string()).unwrap();
+        assert_eq!(board.placement(1), Some((Boat::Vertical(3), Pos { x: 0, y: 0 })));
+    }
+
+    #[test]
+    fn each_length_has_its_own_quota() {
+        let mut board = Board::new(&[4, 3, 2, 1]);
+        assert_eq!(board.remaining_boats(), &[4, 3, 2, 1]);
+
+        // boats of every length, each on its own row, until the quota of that length is used:
+        // one more is refused even where it would fit
+        let mut x = 0;
+        for length in 1..=4 {
+            for placed in 1..=(5 - length) {
+                board.add_boat(Boat::Horizontal(length), Pos { x, y: 0 }).unwrap();
+                assert_eq!(board.remaining_boats()[length - 1] as usize, 5 - length - placed);
+                x += 2;
+            }
+            let before = board.to_string();
+            assert_eq!(board.add_boat(Boat::Vertical(length), Pos { x: 0, y: 10 }), Err(Error::BoatCount));
+            assert_eq!(board.to_string(), before);
+        }
+        assert_eq!(board.remaining_boats(), &[0, 0, 0, 0]);
+        assert!(board.is_complete());
+
+        // lengths outside the fleet have no quota at all
+        assert_eq!(board.add_boat(Boat::Horizontal(5), Pos { x: 17, y: 0 }), Err(Error::BoatCount));
+        assert_eq!(board.add_boat(Boat::Horizontal(0), Pos { x: 17, y: 0 }), Err(Error::BoatCount));
+
+        // a boat taken off the board goes back to the quota of its length
+        board.remove_boat(1).unwrap();
+        assert_eq!(board.remaining_boats(), &[1, 0, 0, 0]);
+        assert!(!board.is_complete());
+    }
+
+    #[test]
+    fn diagonal_contact_is_adjacent() {
+        let mut board = Board::with_rules(6, 6, &[2, 0, 1]).unwrap();
+        board.add_boat(Boat::Horizontal(1), Pos { x: 2, y: 2 }).unwrap();
+
+        // the four corners around the boat, then cells two steps away which don't touch it
+        for (x, y) in [(1, 1), (1, 3), (3, 1), (3, 3)] {
+            assert_eq!(board.check_adjacent(&[Pos { x, y }]), Err(Error::Adjacent(Pos { x: 2, y: 2 })));
+        }
+        for (x, y) in [(0, 0), (0, 4), (4, 0), (4, 4), (2, 4)] {
+            assert_eq!(board.check_adjacent(&[Pos { x, y }]), Ok(()));
+        }
+
+        // a longer boat touching only with the corner of its last cell, against the edge of the board
+        board.add_boat(Boat::Horizontal(1), Pos { x: 5, y: 5 }).unwrap();
+        assert_eq!(board.add_boat(Boat::Vertical(3), Pos { x: 2, y: 4 }), Err(Error::Adjacent(Pos { x: 5, y: 5 })));
+        assert_eq!(board.check_adjacent(&[Pos { x: 0, y: 5 }]), Ok(()));
+    }
+
+    #[test]
+    fn touching_rule_skips_the_adjacency_check() {
+        let mut board = Board::with_rules(6, 6, &[1, 0, 2]).unwrap();
+        board.set_rules(Rules { touching: true, ..Rules::default() }).unwrap();
+        board.add_boat(Boat::Horizontal(3), Pos { x: 2, y: 1 }).unwrap();
+
+        // corner to corner, then side by side with the first boat
+        assert_eq!(board.add_boat(Boat::Horizontal(1), Pos { x: 1, y: 0 }), Ok(()));
+        assert_eq!(board.add_boat(Boat::Horizontal(3), Pos { x: 3, y: 1 }), Ok(()));
+
+        // overlapping is still refused
+        board.remove_boat(3).unwrap();
+        assert_eq!(board.add_boat(Boat::Vertical(3), Pos { x: 0, y: 2 }), Err(Error::Overlap));
+    }
+
+    #[test]
+    fn boats_touch_only_with_the_touching_rule() {
+        let mut classic = Board::with_rules(5, 5, &[2]).unwrap();
+        classic.add_boat(Boat::Horizontal(1), Pos { x: 1, y: 1 }).unwrap();
+        assert_eq!(classic.add_boat(Boat::Horizontal(1), Pos { x: 2, y: 2 }), Err(Error::Adjacent(Pos { x: 1, y: 1 })));
+        assert_eq!(classic.add_boat(Boat::Horizontal(1), Pos { x: 1, y: 2 }), Err(Error::Adjacent(Pos { x: 1, y: 1 })));
+        assert_eq!(classic.add_boat(Boat::Horizontal(1), Pos { x: 3, y: 3 }), Ok(()));
+
+        let mut touching = Board::with_rules(5, 5, &[2]).unwrap();
+        touching.set_rules(Rules { touching: true, ..Rules::default() }).unwrap();
+        touching.add_boat(Boat::Horizontal(1), Pos { x: 1, y: 1 }).unwrap();
+        assert_eq!(touching.add_boat(Boat::Horizontal(1), Pos { x: 1, y: 2 }), Ok(()));
+        assert_eq!(touching.to_string().lines().nth(2), Some(".AB.."));
+    }
+
+    #[test]
+    fn plain_grids_of_the_first_version_are_read() {
+        // saved by the first version after adding a vertical boat of 4 at (0, 0) and a horizontal one of 3 at (18, 17)
+        let mut rows = vec!["0".repeat(BSIZE); BSIZE];
+        for row in rows.iter_mut().take(4) {
+            row.replace_range(0..1, "1");
+        }
+        rows[18].replace_range(17..20, "111");
+        let grid = rows.join("\n") + "\n";
+
+        let board = Board::from(grid.clone()).unwrap();
+        assert_eq!(board.placement(1), Some((Boat::Vertical(4), Pos { x: 0, y: 0 })));
+        assert_eq!(board.placement(2), Some((Boat::Horizontal(3), Pos { x: 18, y: 17 })));
+        assert_eq!(board.remaining_boats(), &[4, 3, 1, 0]);
+        assert_eq!(board.rules(), Rules::default());
+
+        // saved again it is a board file, which reads back the same
+        let saved = board.to_string();
+        assert!(saved.starts_with("battleship v5 size=20x20 fleet=4,3,2,1 remaining=4,3,1,0 "));
+        assert_eq!(Board::from(saved.clone()).unwrap().to_string(), saved);
+
+        // the cells are the ones of the grid
+        let cells: String = saved.lines().skip(1).map(|l| l.replace('.', "0").replace(['A', 'B'], "1") + "\n").collect();
+        assert_eq!(cells, grid);
+    }
+
+    #[test]
+    fn plain_grids_keep_touching_boats_and_refuse_extra_boats() {
+        let mut rows = vec!["0".repeat(BSIZE); BSIZE];
+        rows[0].replace_range(0..3, "111");
+        rows[1].replace_range(3..4, "1");
+        let board = Board::from(rows.join("\n")).unwrap();
+        assert!(board.rules().touching);
+        assert_eq!(board.remaining_boats(), &[3, 3, 1, 1]);
+
+        let mut rows = vec!["0".repeat(BSIZE); BSIZE];
+        rows[0].replace_range(0..4, "1111");
+        rows[2].replace_range(0..4, "1111");
+        assert_eq!(Board::from(rows.join("\n")).err(), Some(ParseError::Quota));
+
+        rows.pop();
+        assert_eq!(Board::from(rows.join("\n")).err(), Some(ParseError::Dimensions));
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_fleet() {
+        let random = |seed: u64| {
+            let mut board = Board::new(&[4, 3, 2, 1]);
+            board.random_fleet(seed).unwrap();
+            board.to_string()
+        };
+
+        assert_eq!(random(7), random(7));
+        assert_ne!(random(7), random(8));
+    }
+
+    #[test]
+    fn a_fleet_that_cannot_fit_is_refused_at_once() {
+        // four boats of 5 apart from each other need more than a 5x5 board
+        let mut board = Board::with_rules(5, 5, &[0, 0, 0, 0, 4]).unwrap();
+        let boats = [Boat::Horizontal(5); 4];
+        assert!(!board.has_room(&boats, &[]));
+        assert_eq!(board.random_fleet(1), Err(Error::NoLayout));
+        assert_eq!(board.remaining_boats(), &[0, 0, 0, 0, 4]);
+
+        // with the touching rule they fit, but not when six cells must stay water
+        board.set_rules(Rules { touching: true, ..Rules::default() }).unwrap();
+        assert_eq!(board.random_fleet(1), Ok(()));
+        let mut board = Board::with_rules(5, 5, &[0, 0, 0, 0, 4]).unwrap();
+        board.set_rules(Rules { touching: true, ..Rules::default() }).unwrap();
+        let water: Vec<Vec<bool>> = (0..5).map(|x| (0..5).map(|y| y == 2 || (x, y) == (0, 0)).collect()).collect();
+        assert!(!board.has_room(&boats, &water));
+        assert_eq!(board.random_fleet_avoiding(1, &water), Err(Error::NoLayout));
+
+        // the same boats on a larger board are placed
+        let mut board = Board::with_rules(9, 5, &[0, 0, 0, 0, 4]).unwrap();
+        assert!(board.has_room(&boats, &[]));
+        assert_eq!(board.random_fleet(1), Ok(()));
+    }
+
+    #[test]
+    fn a_failed_move_or_rotation_leaves_the_board_as_it_was() {
+        let mut board = Board::with_rules(8, 8, &[0, 2, 2]).unwrap();
+        board.add_boat(Boat::Vertical(3), Pos { x: 0, y: 0 }).unwrap();        // A
+        board.add_boat(Boat::Horizontal(3), Pos { x: 0, y: 2 }).unwrap();      // B
+        board.add_boat(Boat::Vertical(2), Pos { x: 3, y: 3 }).unwrap();        // C
+        board.add_boat(Boat::Horizontal(2), Pos { x: 7, y: 5 }).unwrap();      // D
+        let before = board.to_string();
+
+        assert_eq!(board.move_boat(1, Pos { x: 0, y: 3 }), Err(Error::Overlap));
+        assert_eq!(board.move_boat(1, Pos { x: 6, y: 0 }), Err(Error::OutOfBounds));
+        assert_eq!(board.move_boat(1, Pos { x: 5, y: 2 }), Err(Error::Adjacent(Pos { x: 4, y: 3 })));
+        assert_eq!(board.to_string(), before);
+
+        // A turned to the right runs over B, B turned down touches C, D turned down leaves the board
+        assert_eq!(board.rotate_boat(1), Err(Error::Overlap));
+        assert_eq!(board.rotate_boat(2), Err(Error::Adjacent(Pos { x: 3, y: 3 })));
+        assert_eq!(board.rotate_boat(4), Err(Error::OutOfBounds));
+        assert_eq!(board.to_string(), before);
+        assert_eq!(board.remaining_boats(), &[0, 0, 0]);
+    }
+
+    #[test]
+    fn rules_are_kept_in_the_board_file() {
+        let mut board = Board::with_rules(5, 5, &[1]).unwrap();
+        let rules = Rules { salvo: true, touching: false, reveal: true };
+        board.set_rules(rules).unwrap();
+        assert!(board.to_string().starts_with("battleship v5 size=5x5 fleet=1 remaining=1 pieces=0 rules=salvo,reveal\n"));
+        assert_eq!(Board::from(board.to_string()).unwrap().rules(), rules);
+
+        board.add_boat(Boat::Horizontal(1), Pos { x: 0, y: 0 }).unwrap();
+        assert_eq!(board.set_rules(Rules::default()), Err(Error::Rules));
+    }
+}
@@ -0,0 +1,362 @@
+/*
+ * Two players game session
+ * Each player has a board with their own fleet and a view of the opponent's board
+ * The players shoot in turn, player 1 first; every shot is appended to the move log
+ * With the salvo rule a turn is made of one shot for each boat the player still has afloat
+ * The game can be saved and resumed: the file holds the fleets as they were at the start and the log,
+ * the state of the game is rebuilt by replaying the log
+ *
+ * Game file format (version 1)
+ *
+ *   battleship-game v1
+ *   player 1
+ *   <board file of player 1, without shots>
+ *   player 2
+ *   <board file of player 2, without shots>
+ *   moves
+ *   1 3,4 miss
+ *   2 0,0 hit
+ *   1 7,2 sunk:2
+ *   ...
+ *
+ * Players are numbered 1 and 2 in the file and 0 and 1 in the code
+ */
+
+use std::str::FromStr;
+
+use crate::board::{Board, Error, ParseError, Pos, ShotOutcome};
+use crate::target::TargetView;
+
+const FORMAT_HEADER: &str = "battleship-game v1";
+
+pub struct Game {
+    setups: [Board; 2],         // fleets as they were at the start, used to save the game
+    boards: [Board; 2],         // boards[p] = fleet of player p, with the shots of the opponent
+    views: [TargetView; 2],     // views[p] = what player p knows about the opponent's board
+    turn: usize,                // player who shoots next
+    shots_left: usize,          // shots of the current turn still to fire
+    log: Vec<Move>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub player: usize,
+    pub pos: Pos,
+    pub outcome: ShotOutcome,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GameError {
+    NotYourTurn,            // The player tried to shoot out of turn
+    GameOver,               // The game has already a winner
+    Setup,                  // The fleets are not completely placed, already shot, or the boards have different rules
+    Board(Error),           // Invalid shot
+    Parse(ParseError),      // Invalid board in the game file
+    Format(usize),          // Malformed line in the game file (line number, from 1)
+    Replay(usize),          // The logged outcome of a move doesn't match the boards (move number, from 1)
+}
+
+
+impl Game {
+
+    // New game between two boards with the whole fleet placed
+    pub fn new(player1: Board, player2: Board) -> Result<Game, GameError> {
+        let ready = |b: &Board| {
+            b.is_complete()
+                && !(0..b.height()).any(|x| (0..b.width()).any(|y| b.is_shot(Pos { x, y })))
+        };
+        let same_rules = player1.width() == player2.width()
+            && player1.height() == player2.height()
+            && player1.fleet() == player2.fleet()
+            && player1.pieces() == player2.pieces()
+            && player1.piece_fleet() == player2.piece_fleet()
+            && player1.rules() == player2.rules();
+        if !ready(&player1) || !ready(&player2) || !same_rules {
+            return Err(GameError::Setup);
+        }
+
+        let view = TargetView::for_board(&player1);
+        let mut game = Game {
+            setups: [player1.clone(), player2.clone()],
+            boards: [player1, player2],
+            views: [view.clone(), view],
+            turn: 0,
+            shots_left: 0,
+            log: Vec::new(),
+        };
+        game.shots_left = game.shots_per_turn(0);
+
+        Ok(game)
+    }
+
+    // Player shoots at pos on the opponent's board
+    pub fn shoot(&mut self, player: usize, pos: Pos) -> Result<ShotOutcome, GameError> {
+        if self.winner().is_some() {
+            return Err(GameError::GameOver);
+        }
+        if player != self.turn {
+            return Err(GameError::NotYourTurn);
+        }
+
+        let opponent = 1 - player;
+        let outcome = self.boards[opponent].fire(pos).map_err(GameError::Board)?;
+
+        // shooting twice at the same cell is not a move: the turn doesn't change
+        if outcome != ShotOutcome::AlreadyShot {
+            self.views[player].record(pos, outcome);
+            if let (ShotOutcome::Sunk(_), Some(id)) = (outcome, self.boards[opponent].boat_id(pos)) {
+                if self.boards[opponent].rules().reveal {
+                    let boat = self.boards[opponent].boat_cells(id);
+                    self.views[player].reveal(&boat);
+                }
+            }
+            self.log.push(Move { player, pos, outcome });
+
+            self.shots_left -= 1;
+            if self.shots_left == 0 {
+                self.turn = opponent;
+                self.shots_left = self.shots_per_turn(opponent);
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    // Player who shoots next
+    pub fn turn(&self) -> usize {
+        self.turn
+    }
+
+    // Shots the player on turn can still fire before the turn passes
+    pub fn shots_left(&self) -> usize {
+        self.shots_left
+    }
+
+    // One shot per turn, or with the salvo rule one for each boat of the player still afloat
+    fn shots_per_turn(&self, player: usize) -> usize {
+        if self.boards[player].rules().salvo {
+            std::cmp::max(self.boards[player].boats_afloat(), 1)
+        } else {
+            1
+        }
+    }
+
+    // The player who sank the whole opposing fleet, if any
+    pub fn winner(&self) -> Option<usize> {
+        (0..2).find(|&p| self.boards[1 - p].all_sunk())
+    }
+
+    pub fn board(&self, player: usize) -> &Board {
+        &self.boards[player]
+    }
+
+    pub fn view(&self, player: usize) -> &TargetView {
+        &self.views[player]
+    }
+
+    pub fn log(&self) -> &[Move] {
+        &self.log
+    }
+
+    // Rebuild a game from the starting fleets by playing again all the moves, checking the logged outcomes
+    pub fn replay(player1: Board, player2: Board, moves: &[Move]) -> Result<Game, GameError> {
+        let mut game = Game::new(player1, player2)?;
+
+        for (i, m) in moves.iter().enumerate() {
+            match game.shoot(m.player, m.pos) {
+                Ok(outcome) if outcome == m.outcome => {}
+                _ => return Err(GameError::Replay(i + 1)),
+            }
+        }
+
+        Ok(game)
+    }
+
+    // from string to game, see the file format description at the top
+    pub fn from(s: String) -> Result<Game, GameError> {
+        let lines: Vec<&str> = s.lines().collect();
+        let find = |marker: &str| lines.iter().position(|l| *l == marker);
+
+        if lines.first() != Some(&FORMAT_HEADER) {
+            return Err(GameError::Format(1));
+        }
+        let (p1, p2, moves) = match (find("player 1"), find("player 2"), find("moves")) {
+            (Some(p1), Some(p2), Some(moves)) if p1 == 1 && p1 < p2 && p2 < moves => (p1, p2, moves),
+            _ => return Err(GameError::Format(1)),
+        };
+
+        let board = |from: usize, to: usize| Board::from(lines[from + 1..to].join("\n")).map_err(GameError::Parse);
+        let player1 = board(p1, p2)?;
+        let player2 = board(p2, moves)?;
+
+        let mut log = Vec::new();
+        for (i, line) in lines.iter().enumerate().skip(moves + 1) {
+            log.push(line.parse().map_err(|_| GameError::Format(i + 1))?);
+        }
+
+        Game::replay(player1, player2, &log)
+    }
+}
+
+// from game to string
+impl std::fmt::Display for Game {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", FORMAT_HEADER)?;
+        writeln!(f, "player 1")?;
+        write!(f, "{}", self.setups[0])?;
+        writeln!(f, "player 2")?;
+        write!(f, "{}", self.setups[1])?;
+        writeln!(f, "moves")?;
+        for m in self.log.iter() {
+            writeln!(f, "{}", m)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Log line: "1 3,4 sunk:2"
+impl std::fmt::Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {},{} ", self.player + 1, self.pos.x, self.pos.y)?;
+        match self.outcome {
+            ShotOutcome::Miss => write!(f, "miss"),
+            ShotOutcome::Hit => write!(f, "hit"),
+            ShotOutcome::Sunk(length) => write!(f, "sunk:{}", length),
+            ShotOutcome::AlreadyShot => write!(f, "already"),
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Move, ()> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (player, pos, outcome) = match fields.as_slice() {
+            [player, pos, outcome] => (player, pos, outcome),
+            _ => return Err(()),
+        };
+
+        let player = match *player {
+            "1" => 0,
+            "2" => 1,
+            _ => return Err(()),
+        };
+        let (x, y) = pos.split_once(',').ok_or(())?;
+        let pos = Pos { x: x.parse().map_err(|_| ())?, y: y.parse().map_err(|_| ())? };
+        let outcome = match *outcome {
+            "miss" => ShotOutcome::Miss,
+            "hit" => ShotOutcome::Hit,
+            "already" => ShotOutcome::AlreadyShot,
+            sunk => ShotOutcome::Sunk(sunk.strip_prefix("sunk:").ok_or(())?.parse().map_err(|_| ())?),
+        };
+
+        Ok(Move { player, pos, outcome })
+    }
+}
+
+impl std::fmt::Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GameError::NotYourTurn => write!(f, "it is not your turn"),
+            GameError::GameOver => write!(f, "the game is over"),
+            GameError::Setup => write!(f, "both fleets must be completely placed, not shot, on boards with the same rules"),
+            GameError::Board(e) => write!(f, "{}", e),
+            GameError::Parse(e) => write!(f, "invalid board: {}", e),
+            GameError::Format(line) => write!(f, "malformed game file at line {}", line),
+            GameError::Replay(n) => write!(f, "move {} of the log doesn't match the boards", n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Boat, Rules};
+    use crate::target::Mark;
+
+    // 5x5 board with a boat of length 1 in the top left corner and one of length 2 in the bottom right one
+    fn board(rules: Rules) -> Board {
+        let mut board = Board::with_rules(5, 5, &[1, 1]).unwrap();
+        board.set_rules(rules).unwrap();
+        board.add_boat(Boat::Horizontal(1), Pos { x: 0, y: 0 }).unwrap();
+        board.add_boat(Boat::Horizontal(2), Pos { x: 4, y: 3 }).unwrap();
+        board
+    }
+
+    #[test]
+    fn one_shot_per_turn_in_the_classic_game() {
+        let mut game = Game::new(board(Rules::default()), board(Rules::default())).unwrap();
+
+        assert_eq!(game.shots_left(), 1);
+        game.shoot(0, Pos { x: 2, y: 2 }).unwrap();
+        assert_eq!(game.turn(), 1);
+        assert_eq!(game.shoot(0, Pos { x: 2, y: 3 }), Err(GameError::NotYourTurn));
+    }
+
+    #[test]
+    fn salvo_gives_a_shot_per_boat_afloat() {
+        let salvo = Rules { salvo: true, ..Rules::default() };
+        let mut game = Game::new(board(salvo), board(salvo)).unwrap();
+
+        // two boats afloat: two shots, a cell already shot doesn't count
+        assert_eq!(game.shots_left(), 2);
+        assert_eq!(game.shoot(0, Pos { x: 2, y: 2 }), Ok(ShotOutcome::Miss));
+        assert_eq!(game.shoot(0, Pos { x: 2, y: 2 }), Ok(ShotOutcome::AlreadyShot));
+        assert_eq!((game.turn(), game.shots_left()), (0, 1));
+        game.shoot(0, Pos { x: 2, y: 1 }).unwrap();
+
+        // once player 1 has lost a boat they have a shot less
+        assert_eq!((game.turn(), game.shots_left()), (1, 2));
+        assert_eq!(game.shoot(1, Pos { x: 0, y: 0 }), Ok(ShotOutcome::Sunk(1)));
+        game.shoot(1, Pos { x: 2, y: 2 }).unwrap();
+        assert_eq!((game.turn(), game.shots_left()), (0, 1));
+    }
+
+    #[test]
+    fn reveal_shows_the_sunk_boat() {
+        let rules = Rules { touching: true, reveal: true, ..Rules::default() };
+        let mut game = Game::new(board(rules), board(rules)).unwrap();
+
+        game.shoot(0, Pos { x: 4, y: 3 }).unwrap();
+        game.shoot(1, Pos { x: 2, y: 2 }).unwrap();
+        assert_eq!(game.shoot(0, Pos { x: 4, y: 4 }), Ok(ShotOutcome::Sunk(2)));
+        assert_eq!(game.view(0).get(Pos { x: 4, y: 3 }), Mark::Sunk);
+        assert_eq!(game.view(0).get(Pos { x: 4, y: 4 }), Mark::Sunk);
+    }
+
+    #[test]
+    fn a_saved_game_is_replayed_to_the_same_state() {
+        let rules = Rules { salvo: true, reveal: true, ..Rules::default() };
+        let mut game = Game::new(board(rules), board(rules)).unwrap();
+        let shots = [(0, 2, 2), (0, 0, 0), (1, 4, 3), (0, 4, 4)];
+        for (player, x, y) in shots {
+            game.shoot(player, Pos { x, y }).unwrap();
+        }
+        assert_eq!((game.winner(), game.turn(), game.shots_left()), (None, 0, 1));
+
+        let saved = game.to_string();
+        let loaded = Game::from(saved.clone()).unwrap();
+        assert_eq!(loaded.to_string(), saved);
+        assert_eq!(loaded.log(), game.log());
+        assert_eq!((loaded.turn(), loaded.shots_left()), (game.turn(), game.shots_left()));
+        for p in 0..2 {
+            assert_eq!(loaded.board(p).to_string(), game.board(p).to_string());
+            let cells: Vec<Pos> = game.view(p).cells().collect();
+            assert!(cells.iter().all(|&pos| loaded.view(p).get(pos) == game.view(p).get(pos)));
+            assert_eq!(loaded.view(p).afloat(), game.view(p).afloat());
+        }
+
+        // an outcome changed in the log no longer matches the boards
+        let tampered = saved.replace("1 0,0 sunk:1", "1 0,0 miss");
+        assert_ne!(tampered, saved);
+        assert_eq!(Game::from(tampered).err(), Some(GameError::Replay(2)));
+
+        // so does a shot moved to another cell, or played out of turn
+        let tampered = saved.replace("2 4,3 hit", "2 3,3 hit");
+        assert_eq!(Game::from(tampered).err(), Some(GameError::Replay(3)));
+        let tampered = saved.replace("2 4,3 hit", "1 4,3 hit");
+        assert_eq!(Game::from(tampered).err(), Some(GameError::Replay(3)));
+    }
+}
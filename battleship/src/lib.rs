@@ -0,0 +1,17 @@
+pub mod ai;
+pub mod analyze;
+pub mod board;
+pub mod game;
+pub mod net;
+pub mod piece;
+pub mod render;
+pub mod rng;
+pub mod target;
+pub mod validate;
+
+pub use ai::{Ai, Strategy};
+pub use board::{Board, Boat, Error, ParseError, Pos, Rules, ShotOutcome};
+pub use game::{Game, GameError, Move};
+pub use piece::Piece;
+pub use rng::Rng;
+pub use target::{Mark, TargetView};
@@ -1,194 +1,833 @@
-/**
- * Battleship game
- * The game is played on a 20x20 board
- * The player has to place 4 boats on the board
- * The game ends when all the boats are sunk
- * A boat can be represented as a vertical or horizontal line
- * 
+/*
+ * Battleship command line interface
+ * The board is stored in a file, every command loads it, updates it and writes it back
+ * A game between two players is stored in a game file holding both fleets and the log of the moves
+ *
+ *   battleship new <file> <b1>,<b2>,<b3>,<b4>   creates an empty board with b1 boats of length 1, b2 of length 2, ...
+ *       [--width W] [--height H]                the board is 20x20 unless a size is given, the fleet may list any number of lengths
+ *       [--piece <letter>:<count>[:<cells>]]    adds pieces to the fleet: L, T and O are known, other shapes need their cells
+ *       [--rules salvo,touching,reveal]         rule variants: a shot per boat afloat each turn, boats may touch,
+ *                                               sunk boats are shown to the shooter (default: classic, none of them)
+ *       [--force]                               replaces the file if it already exists
+ *   battleship add <file> <len><V|H> <pos>      adds a vertical or horizontal boat starting at pos
+ *   battleship add <file> <letter><r> <pos>     adds a piece turned r times clockwise, its top left corner at pos
+ *   battleship random <file> [--seed N]         places the boats still to place at random
+ *   battleship remove <file> <id>               takes the boat with the given id (a letter) off the board
+ *   battleship move <file> <id> <pos>           moves a boat so that it starts at pos
+ *   battleship rotate <file> <id>               turns a boat around its first cell
+ *   battleship fire <file> <pos>                shoots at pos and prints the outcome
+ *   battleship show <file> [--player P]         draws a board, or for a game file the player's fleet
+ *       [--color]                               next to what they know of the opponent's board
+ *   battleship validate <file> [--fleet F]      lists every problem of a board file or of a grid of 0 and 1
+ *   battleship simulate [--strategy S] [--games N] [--seed N]
+ *                                               average number of shots the computer needs to win
+ *   battleship analyze <file> [--player P]      probability of a boat in each cell over many random fleets with the rules
+ *       [--samples N] [--seed N]                of the board, knowing the shots it has received (for a game file, knowing
+ *       [--csv F] [--color]                     what the player knows of the opponent), drawn as a heatmap and written as CSV
+ *
+ *   battleship start <game> <board1> <board2>   starts a game between two boards with the whole fleet placed
+ *   battleship shoot <game> <player> <pos>      player 1 or 2 shoots at pos on the opponent's board
+ *   battleship status <game>                    whose turn it is and the boats still afloat
+ *   battleship replay <game>                    plays again all the moves of the log
+ *
+ *   battleship serve <board> [--port P]         waits for an opponent on the network, then plays (shooting first)
+ *   battleship join <host>:<port> <board>       plays against an opponent waiting with serve
+ *       [--ai S] [--seed N]                     both: let the computer choose the shots instead of typing them
+ *
+ * A position is written as on the grids drawn by show: the row letter and the column number, e.g. C7
+ * (x,y from 0 is accepted too, so C7 is also 2,6)
+ *
  * Author: Stefano Roy Bisignano
  */
 
-const BSIZE: usize = 20;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use battleship::ai::simulate;
+use battleship::analyze;
+use battleship::net::{self, Event, NetError};
+use battleship::render;
+use battleship::validate::{self, parse_grid};
+use battleship::board::parse_offsets;
+use battleship::{Ai, Board, Boat, Error, Game, GameError, Piece, Pos, Rules, ShotOutcome, Strategy, TargetView};
+use clap::{Parser, Subcommand};
+
+// Exit codes, one for each kind of failure
+const EXIT_IO: u8 = 1;
+const EXIT_OVERLAP: u8 = 3;
+const EXIT_OUT_OF_BOUNDS: u8 = 4;
+const EXIT_BOAT_COUNT: u8 = 5;
+const EXIT_ADJACENT: u8 = 6;
+const EXIT_FORMAT: u8 = 7;
+const EXIT_RULES: u8 = 8;
+const EXIT_NO_LAYOUT: u8 = 9;
+const EXIT_NOT_YOUR_TURN: u8 = 10;
+const EXIT_GAME_OVER: u8 = 11;
+const EXIT_SETUP: u8 = 12;
+const EXIT_REPLAY: u8 = 13;
+const EXIT_NETWORK: u8 = 14;
+const EXIT_CHEATING: u8 = 15;
+const EXIT_UNKNOWN_BOAT: u8 = 16;
+const EXIT_BOAT_HIT: u8 = 17;
+const EXIT_INVALID: u8 = 18;
+
+#[derive(Parser)]
+#[command(name = "battleship", about = "Manage a battleship board stored in a file")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
 
-// Data structures
-pub struct Board {  
-    boats: [u8; 4],                    
-    data: [[u8; BSIZE]; BSIZE],        
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new empty board
+    New {
+        /// Board file
+        file: String,
+        /// Number of boats of length 1, 2, 3, ..., e.g. 4,3,2,1 or 0,1,2,1,1 for a fleet with a carrier
+        #[arg(value_delimiter = ',', num_args = 1..)]
+        boats: Vec<u8>,
+        /// Number of columns
+        #[arg(long, default_value_t = 20)]
+        width: usize,
+        /// Number of rows
+        #[arg(long, default_value_t = 20)]
+        height: usize,
+        /// Pieces in the fleet, e.g. L:2 or U:1:0,0/0,2/1,0/1,1/1,2 for a shape given by its cells
+        #[arg(long = "piece", value_parser = parse_piece)]
+        pieces: Vec<(Piece, u8)>,
+        /// Rule variants, e.g. salvo,reveal: salvo, touching, reveal or classic for none
+        #[arg(long, default_value = "classic")]
+        rules: Rules,
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Add a boat to an existing board
+    Add {
+        /// Board file
+        file: String,
+        /// Boat length and direction, e.g. 3V or 2H, or piece letter and rotation, e.g. L0 or T3
+        #[arg(value_parser = parse_boat)]
+        boat: Boat,
+        /// Starting position, e.g. C7
+        #[arg(value_parser = parse_pos)]
+        pos: Pos,
+    },
+    /// Place the boats still to place at random, without overlaps or, unless the rules allow it, touching boats
+    Random {
+        /// Board file
+        file: String,
+        /// Seed of the random generator, the same seed gives the same board (default: current time)
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Take a boat off the board
+    Remove {
+        /// Board file
+        file: String,
+        /// Boat id, the letter shown in the board file
+        #[arg(value_parser = parse_id)]
+        id: u8,
+    },
+    /// Move a boat to a new position, keeping its direction
+    Move {
+        /// Board file
+        file: String,
+        /// Boat id, the letter shown in the board file
+        #[arg(value_parser = parse_id)]
+        id: u8,
+        /// New starting position, e.g. C7
+        #[arg(value_parser = parse_pos)]
+        pos: Pos,
+    },
+    /// Turn a boat from vertical to horizontal or the other way round
+    Rotate {
+        /// Board file
+        file: String,
+        /// Boat id, the letter shown in the board file
+        #[arg(value_parser = parse_id)]
+        id: u8,
+    },
+    /// Shoot at a cell of the board
+    Fire {
+        /// Board file
+        file: String,
+        /// Target position, e.g. C7
+        #[arg(value_parser = parse_pos)]
+        pos: Pos,
+    },
+    /// Draw a board, or both grids of a player in a game
+    Show {
+        /// Board or game file
+        file: String,
+        /// Player whose grids are shown, for a game file
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        player: u8,
+        /// Use colours
+        #[arg(long)]
+        color: bool,
+    },
+    /// Check the boats of a board file, or of a plain grid of 0 and 1, against the rules
+    Validate {
+        /// Board file or grid
+        file: String,
+        /// Number of boats of length 1, 2, 3, ... expected (default: the boats placed according to the header, or 4,3,2,1)
+        #[arg(long, value_delimiter = ',')]
+        fleet: Option<Vec<u8>>,
+    },
+    /// Let the computer play against random fleets and report how many shots it needs
+    Simulate {
+        /// Shooting strategy: random, hunt or density
+        #[arg(long, default_value = "density")]
+        strategy: Strategy,
+        /// Number of games
+        #[arg(long, default_value_t = 100)]
+        games: usize,
+        /// Seed of the random generator, the same seed gives the same games
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Number of boats of length 1, 2, 3, ...
+        #[arg(long, value_delimiter = ',', default_value = "4,3,2,1")]
+        fleet: Vec<u8>,
+        /// Number of columns
+        #[arg(long, default_value_t = 20)]
+        width: usize,
+        /// Number of rows
+        #[arg(long, default_value_t = 20)]
+        height: usize,
+    },
+    /// Probability of a boat in each cell, from random fleets that agree with the shots already fired
+    Analyze {
+        /// Board file (its rules and the shots it has received, not its boats) or game file
+        file: String,
+        /// Player whose knowledge of the opponent is used, for a game file
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        player: u8,
+        /// Number of random fleets
+        #[arg(long, default_value_t = 10_000)]
+        samples: usize,
+        /// Seed of the random generator, the same seed gives the same fleets
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Write the probabilities to this CSV file
+        #[arg(long)]
+        csv: Option<String>,
+        /// Use colours
+        #[arg(long)]
+        color: bool,
+    },
+    /// Start a game between two boards
+    Start {
+        /// Game file
+        game: String,
+        /// Board file of player 1
+        board1: String,
+        /// Board file of player 2
+        board2: String,
+    },
+    /// Shoot at the opponent's board
+    Shoot {
+        /// Game file
+        game: String,
+        /// Player shooting, 1 or 2
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=2))]
+        player: u8,
+        /// Target position, e.g. C7
+        #[arg(value_parser = parse_pos)]
+        pos: Pos,
+    },
+    /// Show the state of a game
+    Status {
+        /// Game file
+        game: String,
+    },
+    /// Play again all the moves of a game
+    Replay {
+        /// Game file
+        game: String,
+    },
+    /// Wait for an opponent on the network and play
+    Serve {
+        /// Board file with the whole fleet placed
+        board: String,
+        /// Port to listen on
+        #[arg(long, default_value_t = 7878)]
+        port: u16,
+        /// Let the computer shoot with this strategy: random, hunt or density
+        #[arg(long)]
+        ai: Option<Strategy>,
+        /// Seed of the computer player
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Connect to an opponent waiting with serve and play
+    Join {
+        /// Address of the opponent, as host:port
+        address: String,
+        /// Board file with the whole fleet placed
+        board: String,
+        /// Let the computer shoot with this strategy: random, hunt or density
+        #[arg(long)]
+        ai: Option<Strategy>,
+        /// Seed of the computer player
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+}
 
+// Shots typed on the terminal, asked again until they are inside the opponent's board
+struct Human {
+    view: TargetView,
 }
 
-pub enum Boat {         // A boat can be represented as a vertical or horizontal line
-    Vertical(usize),
-    Horizontal(usize),
+impl Ai for Human {
+    fn next_shot(&mut self) -> Option<Pos> {
+        let stdin = io::stdin();
+        loop {
+            print!("Your shot (e.g. C7): ");
+            io::stdout().flush().ok()?;
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            match parse_pos(line.trim()) {
+                Ok(pos) if pos.x < self.view.height() && pos.y < self.view.width() => return Some(pos),
+                Ok(pos) => println!(
+                    "{} is outside the board: rows go from A to {}, columns from 1 to {}",
+                    render::cell_label(pos),
+                    render::row_label(self.view.height() - 1),
+                    self.view.width()
+                ),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    fn record(&mut self, pos: Pos, outcome: ShotOutcome) {
+        self.view.record(pos, outcome);
+    }
+
+    fn reveal(&mut self, boat: &[Pos]) {
+        self.view.reveal(boat);
+    }
 }
 
-pub struct Pos{
-    x: usize,
-    y: usize,
+// "3V" -> Boat::Vertical(3), "2H" -> Boat::Horizontal(2), "L1" -> Boat::Piece('L', 1)
+fn parse_boat(s: &str) -> Result<Boat, String> {
+    if let Some(name) = s.chars().next().filter(|c| c.is_ascii_alphabetic()) {
+        let rotation = match &s[1..] {
+            "" => 0,
+            r => r.parse().map_err(|_| format!("invalid piece rotation in '{}'", s))?,
+        };
+        return Ok(Boat::Piece(name, rotation));
+    }
+
+    // the direction is the last character, which may not be a single byte
+    let (length, dir) = s.split_at(s.char_indices().last().map_or(0, |(i, _)| i));
+    let length: usize = length.parse().map_err(|_| format!("invalid boat length in '{}'", s))?;
+
+    match dir {
+        "V" | "v" => Ok(Boat::Vertical(length)),
+        "H" | "h" => Ok(Boat::Horizontal(length)),
+        _ => Err(format!("invalid boat direction in '{}', expected V or H", s)),
+    }
 }
 
-pub enum Error {
-    Overlap,      // Boat overlaps
-    OutOfBounds,  // Boat out of bounds
-    BoatCount,    // Number of boats exceeded
+// "L:2" -> two standard L pieces, "U:1:0,0/0,2/1,0/1,1/1,2" -> one piece with the given cells
+fn parse_piece(s: &str) -> Result<(Piece, u8), String> {
+    let mut fields = s.splitn(3, ':');
+    let (name, count, cells) = (fields.next().unwrap_or(""), fields.next(), fields.next());
+
+    let mut letters = name.chars();
+    let name = match (letters.next(), letters.next()) {
+        (Some(c), None) => c,
+        _ => return Err(format!("invalid piece letter in '{}'", s)),
+    };
+    let count = count.ok_or(format!("missing piece count in '{}', expected <letter>:<count>", s))?;
+    let count = count.parse().map_err(|_| format!("invalid piece count in '{}'", s))?;
+
+    let piece = match cells {
+        Some(cells) => parse_offsets(cells).and_then(|cells| Piece::new(name, &cells)),
+        None => Piece::standard(name),
+    };
+    let piece = piece.ok_or(format!("invalid piece '{}': the cells must be a connected shape that is not a straight line", s))?;
+
+    Ok((piece, count))
 }
 
+// "C7" -> Pos { x: 2, y: 6 }, as labelled on the grids; "3,4" -> Pos { x: 3, y: 4 }
+fn parse_pos(s: &str) -> Result<Pos, String> {
+    if let Some(row) = s.chars().next().filter(|c| c.is_ascii_alphabetic()) {
+        let column: usize = s[1..].parse().map_err(|_| format!("invalid column in '{}', expected e.g. C7", s))?;
+        let column = column.checked_sub(1).ok_or(format!("invalid column in '{}', columns start from 1", s))?;
+        return Ok(Pos { x: (row.to_ascii_uppercase() as u8 - b'A') as usize, y: column });
+    }
 
-// Board structure implementation
-impl Board {
+    let (x, y) = s.split_once(',').ok_or(format!("invalid position '{}', expected e.g. C7 or x,y", s))?;
+    let x = x.trim().parse().map_err(|_| format!("invalid x in '{}'", s))?;
+    let y = y.trim().parse().map_err(|_| format!("invalid y in '{}'", s))?;
 
-    pub fn new(boats: &[u8]) -> Board {
-        let mut new_board = Board {
-            boats: [0; 4],
-            data: [[0; BSIZE]; BSIZE],
-        };
+    Ok(Pos { x, y })
+}
 
-        let len = std::cmp::min(boats.len(), 4);
-        new_board.boats[..len].copy_from_slice(&boats[..len]);
+// "C" -> 3, boats are written in the board file as letters
+fn parse_id(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [c @ b'A'..=b'Z'] => Ok(c - b'A' + 1),
+        [c @ b'a'..=b'z'] => Ok(c - b'a' + 1),
+        _ => Err(format!("invalid boat id '{}', expected a letter", s)),
+    }
+}
 
-        new_board
-    }   
+// Exit code for a board error
+fn error_code(e: &Error) -> u8 {
+    match e {
+        Error::Overlap => EXIT_OVERLAP,
+        Error::OutOfBounds => EXIT_OUT_OF_BOUNDS,
+        Error::BoatCount => EXIT_BOAT_COUNT,
+        Error::Adjacent(_) => EXIT_ADJACENT,
+        Error::Rules => EXIT_RULES,
+        Error::NoLayout => EXIT_NO_LAYOUT,
+        Error::UnknownBoat => EXIT_UNKNOWN_BOAT,
+        Error::BoatHit => EXIT_BOAT_HIT,
+    }
+}
 
-    pub fn add_boat(&mut self, boat: Boat, pos: Pos) -> Result<(), Error> {
-        // being self in the function is needed to create a new board with let mut 
-        let new_board = self;
+// Exit code for a game error
+fn game_error_code(e: &GameError) -> u8 {
+    match e {
+        GameError::NotYourTurn => EXIT_NOT_YOUR_TURN,
+        GameError::GameOver => EXIT_GAME_OVER,
+        GameError::Setup => EXIT_SETUP,
+        GameError::Board(e) => error_code(e),
+        GameError::Parse(_) | GameError::Format(_) => EXIT_FORMAT,
+        GameError::Replay(_) => EXIT_REPLAY,
+    }
+}
 
-        // Switch case to check the type of boat (vertical or horizontal)
-        match boat {
-            
-            // Vertical boat case
-            Boat::Vertical(length) => {
-                // add vertical boat with a given length at position pos  
-                new_board.add_vertical_boat(length, pos)?;
-            }
+fn load(file: &str) -> Result<Board, u8> {
+    let s = fs::read_to_string(file).map_err(|e| {
+        eprintln!("Error: cannot read {}: {}", file, e);
+        EXIT_IO
+    })?;
 
-            // Horizontal boat case
-            Boat::Horizontal(length) => {
-                // add horizontal boat with a given length at position pos
-                new_board.add_horizontal_boat(length, pos)?;
-            }
-        }
+    Board::from(s).map_err(|e| {
+        eprintln!("Error: invalid board file {}: {}", file, e);
+        EXIT_FORMAT
+    })
+}
+
+fn save(file: &str, board: &Board) -> Result<(), u8> {
+    fs::write(file, board.to_string()).map_err(|e| {
+        eprintln!("Error: cannot write {}: {}", file, e);
+        EXIT_IO
+    })
+}
 
-        Ok(())
+// Load a board, change it and write it back
+fn edit(file: &str, change: impl FnOnce(&mut Board) -> Result<(), Error>) -> Result<(), u8> {
+    let mut board = load(file)?;
+
+    change(&mut board).map_err(|e| {
+        eprintln!("Error: {}", e);
+        error_code(&e)
+    })?;
+    save(file, &board)?;
+
+    println!("Boats left to place (length 1 to {}): {:?}", board.remaining_boats().len(), board.remaining_boats());
+    for (piece, left) in board.pieces().iter().zip(board.remaining_pieces()) {
+        println!("Pieces {} left to place: {}", piece.name(), left);
     }
+    Ok(())
+}
+
+fn load_game(file: &str) -> Result<Game, u8> {
+    let s = fs::read_to_string(file).map_err(|e| {
+        eprintln!("Error: cannot read {}: {}", file, e);
+        EXIT_IO
+    })?;
+
+    Game::from(s).map_err(|e| {
+        eprintln!("Error: invalid game file {}: {}", file, e);
+        game_error_code(&e)
+    })
+}
 
-    /**
-     * Helper functions:
-     * 
-     * is_occupied checks if a cell is occupied or not
-     * place_boat places a boat on the board
-     * add_vertical_boat adds a vertical boat to the board
-     * add_horizontal_boat adds a horizontal boat to the board
-     */
+// Play a network game on a connected stream
+fn play_online(stream: TcpStream, board: Board, ai: Option<Strategy>, seed: u64, first: bool) -> Result<(), u8> {
+    let net_error = |e: NetError| {
+        eprintln!("Error: {}", e);
+        match e {
+            NetError::Rules => EXIT_RULES,
+            NetError::Setup => EXIT_SETUP,
+            NetError::Cheating(_) => EXIT_CHEATING,
+            _ => EXIT_NETWORK,
+        }
+    };
+
+    let mut shooter: Box<dyn Ai> = match ai {
+        Some(strategy) => strategy.create(&board, seed),
+        None => Box::new(Human { view: TargetView::for_board(&board) }),
+    };
+    let reader = BufReader::new(stream.try_clone().map_err(|e| net_error(NetError::Io(e)))?);
+
+    let won = net::play(reader, stream, board, shooter.as_mut(), first, &mut |event| match event {
+        Event::Fired(pos, outcome) => println!("You shoot at {}: {:?}", render::cell_label(pos), outcome),
+        Event::Received(pos, outcome) => println!("Opponent shoots at {}: {:?}", render::cell_label(pos), outcome),
+    })
+    .map_err(net_error)?;
+
+    println!("{} The opponent's board has been verified.", if won { "You win!" } else { "You lose." });
+    Ok(())
+}
 
-    fn is_occupied(&self, pos: Pos) -> bool {
-        self.data[pos.x][pos.y] != 0
+fn print_status(game: &Game) {
+    match game.winner() {
+        Some(p) => println!("Player {} wins", p + 1),
+        None => println!("Turn of player {}", game.turn() + 1),
+    }
+    if game.board(0).rules().salvo && game.winner().is_none() {
+        println!("Shots left in this turn: {}", game.shots_left());
     }
+    println!("Rules: {}", game.board(0).rules());
+    println!("Moves played: {}", game.log().len());
 
-    fn place_boat(&mut self, pos: Pos) -> () {
-        self.data[pos.x][pos.y] = 1;
+    // the boats afloat of a player are the ones the opponent hasn't sunk yet
+    for p in 0..2 {
+        println!("Player {}: boats afloat (length 1 to {}): {:?}", p + 1, game.view(1 - p).afloat().len(), game.view(1 - p).afloat());
     }
-    
-    fn add_vertical_boat(&mut self, length: usize, pos: Pos) -> Result<(), Error> {
-        
-        if pos.x + length > BSIZE {
-            return Err(Error::OutOfBounds);
-        }
+}
 
-        for i in 0..length {
-            if self.is_occupied(Pos {x: pos.x + i, y: pos.y }) {
-                return Err(Error::Overlap);
+fn run(command: Command) -> Result<(), u8> {
+    match command {
+        Command::New { file, boats, width, height, pieces, rules, force } => {
+            let mut board = Board::with_pieces(width, height, &boats, &pieces).map_err(|e| {
+                eprintln!("Error: {}", e);
+                error_code(&e)
+            })?;
+            board.set_rules(rules).map_err(|e| {
+                eprintln!("Error: {}", e);
+                error_code(&e)
+            })?;
+
+            // a board that already exists is only replaced on request
+            let mut options = OpenOptions::new();
+            options.write(true);
+            if force {
+                options.create(true).truncate(true);
+            } else {
+                options.create_new(true);
             }
+            let mut f = options.open(&file).map_err(|e| {
+                match e.kind() {
+                    io::ErrorKind::AlreadyExists => eprintln!("Error: {} already exists, use --force to replace it", file),
+                    _ => eprintln!("Error: cannot write {}: {}", file, e),
+                }
+                EXIT_IO
+            })?;
+            write!(f, "{}", board).map_err(|e| {
+                eprintln!("Error: cannot write {}: {}", file, e);
+                EXIT_IO
+            })?;
         }
-    
-        for i in 0..length {
-            self.place_boat(Pos { x: pos.x + i, y: pos.y });
+
+        Command::Add { file, boat, pos } => {
+            edit(&file, |board| board.add_boat(boat, pos))?;
         }
-    
-        Ok(())
-    }
-    
 
-    fn add_horizontal_boat(&mut self, length: usize, pos: Pos) -> Result<(), Error> {
+        Command::Random { file, seed } => {
+            let mut board = load(&file)?;
+            let seed = seed.unwrap_or_else(|| {
+                SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+            });
+
+            board.random_fleet(seed).map_err(|e| {
+                eprintln!("Error: {}", e);
+                error_code(&e)
+            })?;
+            save(&file, &board)?;
 
-        if pos.y + length > BSIZE {
-            return Err(Error::OutOfBounds);
+            println!("Boats placed with seed {}", seed);
         }
-    
-        for i in 0..length {
-            if self.is_occupied(Pos { x: pos.x, y: pos.y + i }) {
-                return Err(Error::Overlap);
-            }
+
+        Command::Remove { file, id } => {
+            edit(&file, |board| board.remove_boat(id).map(|_| ()))?;
         }
-    
-        for i in 0..length {
-            self.place_boat(Pos { x: pos.x, y: pos.y + i });
+
+        Command::Move { file, id, pos } => {
+            edit(&file, |board| board.move_boat(id, pos))?;
         }
 
-        Ok(())
-    }
+        Command::Rotate { file, id } => {
+            edit(&file, |board| board.rotate_boat(id))?;
+        }
+
+        Command::Fire { file, pos } => {
+            let mut board = load(&file)?;
 
+            let outcome = board.fire(pos).map_err(|e| {
+                eprintln!("Error: {}", e);
+                error_code(&e)
+            })?;
+            save(&file, &board)?;
+
+            match outcome {
+                ShotOutcome::Miss => println!("Miss"),
+                ShotOutcome::Hit => println!("Hit"),
+                ShotOutcome::Sunk(length) => println!("Hit and sunk a boat of length {}", length),
+                ShotOutcome::AlreadyShot => println!("Cell already shot"),
+            }
+            if board.all_sunk() {
+                println!("All boats sunk: game over");
+            }
+        }
 
-    // from string to board
-    pub fn from(s: String) -> Board {
-        let mut board = Board::new(&[]);
-        
-        for (i, line) in s.lines().enumerate() {
-            for (j, c) in line.chars().enumerate() {
-                if c == '1' {
-                    board.data[i][j] = 1;
+        Command::Show { file, player, color } => {
+            let s = fs::read_to_string(&file).map_err(|e| {
+                eprintln!("Error: cannot read {}: {}", file, e);
+                EXIT_IO
+            })?;
+
+            if s.starts_with("battleship-game") {
+                let game = load_game(&file)?;
+                let player = player as usize - 1;
+                let own = render::own_grid(game.board(player), color);
+                let target = render::target_grid(game.view(player), color);
+
+                print!("{}", render::side_by_side("Your fleet", &own, "Opponent", &target));
+            } else {
+                let board = load(&file)?;
+                for line in render::own_grid(&board, color) {
+                    println!("{}", line);
                 }
             }
+            println!();
+            println!("{}", render::legend(color));
         }
 
-        board
-    }
+        Command::Validate { file, fleet } => {
+            let s = fs::read_to_string(&file).map_err(|e| {
+                eprintln!("Error: cannot read {}: {}", file, e);
+                EXIT_IO
+            })?;
+            let grid = parse_grid(&s).map_err(|e| {
+                eprintln!("Error: invalid grid {}: {}", file, e);
+                EXIT_FORMAT
+            })?;
+
+            // when boats may touch only the ids in the board file tell them apart
+            let report = match Board::from(s.clone()) {
+                Ok(board) if fleet.is_none() && board.rules().touching => board.validate(),
+                _ => {
+                    let expected = fleet.or(grid.placed).unwrap_or_else(|| vec![4, 3, 2, 1]);
+                    validate::validate(&grid.occupied, &expected, &grid.pieces)
+                }
+            };
+
+            println!("{} boats found", report.boats.len());
+            for violation in report.violations.iter() {
+                println!("{}", violation);
+            }
+            if !report.is_valid() {
+                return Err(EXIT_INVALID);
+            }
+            println!("The board is valid");
+        }
+
+        Command::Simulate { strategy, games, seed, fleet, width, height } => {
+            let report = simulate(strategy, width, height, &fleet, games, seed).map_err(|e| {
+                eprintln!("Error: {}", e);
+                error_code(&e)
+            })?;
+
+            println!("Strategy {:?}, {} games", strategy, report.games);
+            println!("Average shots to win: {:.2} (best {}, worst {})", report.average, report.best, report.worst);
+        }
 
-    // from board to string
-    pub fn to_string(&self) -> String {
-        let mut s = String::new();
+        Command::Analyze { file, player, samples, seed, csv, color } => {
+            let s = fs::read_to_string(&file).map_err(|e| {
+                eprintln!("Error: cannot read {}: {}", file, e);
+                EXIT_IO
+            })?;
+
+            let (rules, view) = if s.starts_with("battleship-game") {
+                let game = load_game(&file)?;
+                let player = player as usize - 1;
+                (game.board(1 - player).cleared(), game.view(player).clone())
+            } else {
+                let board = load(&file)?;
+                (board.cleared(), TargetView::of_board(&board))
+            };
+
+            let heatmap = analyze::posterior(&rules, &view, samples, seed).map_err(|e| {
+                eprintln!("Error: {}", e);
+                error_code(&e)
+            })?;
+            if let Some(csv) = csv {
+                fs::write(&csv, heatmap.to_csv()).map_err(|e| {
+                    eprintln!("Error: cannot write {}: {}", csv, e);
+                    EXIT_IO
+                })?;
+            }
 
-        for row in self.data.iter() {
-            for cell in row.iter()   {
-                s += &cell.to_string();
+            for line in render::heatmap(&heatmap.probabilities(), color) {
+                println!("{}", line);
             }
-            s += "\n";
+            println!();
+            println!("{} of {} random fleets agree with the shots fired", heatmap.accepted, heatmap.samples);
         }
 
-        s
-    }
-    
-}
+        Command::Start { game, board1, board2 } => {
+            let new_game = Game::new(load(&board1)?, load(&board2)?).map_err(|e| {
+                eprintln!("Error: {}", e);
+                game_error_code(&e)
+            })?;
+
+            fs::write(&game, new_game.to_string()).map_err(|e| {
+                eprintln!("Error: cannot write {}: {}", game, e);
+                EXIT_IO
+            })?;
+            print_status(&new_game);
+        }
+
+        Command::Shoot { game: file, player, pos } => {
+            let mut game = load_game(&file)?;
+            let player = player as usize - 1;
+
+            let outcome = game.shoot(player, pos).map_err(|e| {
+                eprintln!("Error: {}", e);
+                game_error_code(&e)
+            })?;
+
+            // the log is append only: the new move goes at the end of the file
+            if let Some(m) = game.log().last().filter(|_| outcome != ShotOutcome::AlreadyShot) {
+                let mut f = OpenOptions::new().append(true).open(&file).map_err(|e| {
+                    eprintln!("Error: cannot write {}: {}", file, e);
+                    EXIT_IO
+                })?;
+                writeln!(f, "{}", m).map_err(|e| {
+                    eprintln!("Error: cannot write {}: {}", file, e);
+                    EXIT_IO
+                })?;
+            }
+
+            match outcome {
+                ShotOutcome::Miss => println!("Miss"),
+                ShotOutcome::Hit => println!("Hit"),
+                ShotOutcome::Sunk(length) => println!("Hit and sunk a boat of length {}", length),
+                ShotOutcome::AlreadyShot => println!("Cell already shot, shoot again"),
+            }
+            if let Some(p) = game.winner() {
+                println!("Player {} wins", p + 1);
+            } else if game.turn() == player && outcome != ShotOutcome::AlreadyShot {
+                println!("Shots left in this turn: {}", game.shots_left());
+            }
+        }
+
+        Command::Status { game } => {
+            print_status(&load_game(&game)?);
+        }
+
+        Command::Replay { game } => {
+            let game = load_game(&game)?;
+
+            for (i, m) in game.log().iter().enumerate() {
+                println!("{:4}. player {} shoots at {}: {:?}", i + 1, m.player + 1, render::cell_label(m.pos), m.outcome);
+            }
+            print_status(&game);
+        }
+
+        Command::Serve { board, port, ai, seed } => {
+            let board = load(&board)?;
+            let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|e| {
+                eprintln!("Error: cannot listen on port {}: {}", port, e);
+                EXIT_NETWORK
+            })?;
+
+            println!("Waiting for an opponent on port {}...", port);
+            let (stream, address) = listener.accept().map_err(|e| {
+                eprintln!("Error: {}", e);
+                EXIT_NETWORK
+            })?;
+            println!("Playing against {}", address);
+
+            play_online(stream, board, ai, seed, true)?;
+        }
+
+        Command::Join { address, board, ai, seed } => {
+            let board = load(&board)?;
+            let stream = TcpStream::connect(&address).map_err(|e| {
+                eprintln!("Error: cannot connect to {}: {}", address, e);
+                EXIT_NETWORK
+            })?;
+            println!("Playing against {}", address);
 
+            play_online(stream, board, ai, seed, false)?;
+        }
+    }
 
+    Ok(())
+}
 
+fn main() -> ExitCode {
+    let args = Args::parse();
 
+    match run(args.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
 
-fn main() {
-    // Boats to add to the board
-    let boats = [4, 3, 2, 1];
-    // Create a board
-    let mut board = Board::new(&boats);
-    // Initial positions of the boats
-    let positions: Vec<(Boat, Pos)> = vec![
-        (Boat::Vertical(4), Pos { x: 0, y: 0 }),
-        (Boat::Horizontal(3), Pos { x: 18, y: 18 }),
-    ];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Add the boats to the board
-    for (boat, pos) in positions {
-        let result= board.add_boat(boat, pos);
+    fn add(boat: &str) -> Result<Args, clap::Error> {
+        Args::try_parse_from(["battleship", "add", "board.txt", boat, "C7"])
+    }
 
-        match result {
-            Ok(()) => {
-                println!("{}", board.to_string());
-            },
-            Err(e) => match e {
-                Error::Overlap => println!("Error: boat overlap."),
-                Error::OutOfBounds => println!("Error: boat out of bounds."),
-                Error::BoatCount => println!("Error: too many boats."),
-            },
+    #[test]
+    fn non_ascii_boats_are_refused_without_panicking() {
+        for (boat, message) in [
+            ("3é", "invalid boat direction in '3é'"),
+            ("é", "invalid boat length in 'é'"),
+            ("éV", "invalid boat length in 'éV'"),
+            ("3V\u{301}", "invalid boat length in '3V\u{301}'"),
+            ("L½", "invalid piece rotation in 'L½'"),
+        ] {
+            let error = add(boat).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+            assert!(error.to_string().contains(message), "{}", error);
         }
+
+        let args = add("3V").unwrap();
+        assert!(matches!(args.command, Command::Add { boat: Boat::Vertical(3), pos: Pos { x: 2, y: 6 }, .. }));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn new_keeps_an_existing_board_unless_forced() {
+        let file = std::env::temp_dir().join(format!("battleship-new-{}.txt", std::process::id()));
+        let file = file.to_str().unwrap();
+        let new = |fleet: &str, force: bool| {
+            let mut args = vec!["battleship", "new", file, fleet];
+            if force {
+                args.push("--force");
+            }
+            run(Args::try_parse_from(args).unwrap().command)
+        };
+
+        assert_eq!(new("1,1", false), Ok(()));
+        let first = fs::read_to_string(file).unwrap();
+        assert_eq!(new("2,2", false), Err(EXIT_IO));
+        assert_eq!(fs::read_to_string(file).unwrap(), first);
+
+        assert_eq!(new("2,2", true), Ok(()));
+        assert!(fs::read_to_string(file).unwrap().contains(" fleet=2,2 "));
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,480 @@
+/*
+ * Networked game over a line based text protocol
+ * Each side keeps its board private and only exchanges shots and their outcomes
+ * To prevent cheating every side commits to its board at the start by sending sha256(salt + board)
+ * and reveals salt and board at the end: the reported outcomes are checked against the revealed board
+ *
+ * Protocol (one message per line, the side that accepted the connection shoots first)
+ *
+ *   HELLO v2 <width>x<height> <fleet> <rules> <commitment>     both sides, at the start
+ *   FIRE <x>,<y>                                               attacker
+ *   RESULT miss|hit|sunk:<length> [<cells>]|already            defender, the turn passes unless already
+ *   REVEAL <salt> <board>                                      both sides, when a fleet has been sunk
+ *   ERROR <message>                                            either side, then the connection is closed
+ *
+ * The turn passes after one shot, or with the salvo rule after one shot for each boat the attacker has afloat
+ * With the reveal rule a sunk result lists the cells of the boat, e.g. sunk:2 3,4/3,5
+ * In REVEAL the lines of the board file are joined with '|'
+ * When the fleet has pieces, each is appended to the fleet in HELLO as +<letter>:<count>:<cells>, e.g. 4,3,2,1+O:1:0,0/0,1/1,0/1,1
+ */
+
+use std::io::{self, BufRead, Read, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::ai::Ai;
+use crate::board::{format_offsets, parse_offsets, Board, Pos, ShotOutcome};
+use crate::target::TargetView;
+
+const PROTOCOL_VERSION: &str = "v2";
+const SALT_BYTES: usize = 32;       // 256 random bits hide the board behind the commitment
+const MAX_LINE: usize = 4096;       // longest message accepted, a 26x26 board in REVEAL is well below
+
+// What happened during the game, for the user interface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Fired(Pos, ShotOutcome),        // We shot at the opponent
+    Received(Pos, ShotOutcome),     // The opponent shot at us
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),          // Connection error
+    Disconnected,           // The opponent closed the connection
+    Protocol(String),       // Unexpected or malformed message
+    Rules,                  // The two boards have different size or fleet
+    Setup,                  // Our fleet is not completely placed
+    NoShot,                 // The shooter has nothing left to shoot
+    Cheating(String),       // The opponent's revealed board doesn't match what they reported
+}
+
+// Play a whole game on a connection, returns true if we won
+// shooter chooses our shots, events is called after every shot
+pub fn play<R: BufRead, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    board: Board,
+    shooter: &mut dyn Ai,
+    first: bool,
+    events: &mut dyn FnMut(Event),
+) -> Result<bool, NetError> {
+    let shot = (0..board.height()).any(|x| (0..board.width()).any(|y| board.is_shot(Pos { x, y })));
+    if shot || !board.is_complete() {
+        return Err(NetError::Setup);
+    }
+
+    // Commit to our board
+    let setup = board.to_string();
+    let salt = random_salt()?;
+    let rules = format!("{}x{} {} {}", board.width(), board.height(), format_fleet(&board), board.rules());
+    send(&mut writer, &format!("HELLO {} {} {}", PROTOCOL_VERSION, rules, commitment(&salt, &setup)))?;
+
+    let hello = receive(&mut reader)?;
+    let their_commitment = match hello.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["HELLO", PROTOCOL_VERSION, size, fleet, variants, commitment] => {
+            if format!("{} {} {}", size, fleet, variants) != rules {
+                let _ = send(&mut writer, "ERROR different rules");
+                return Err(NetError::Rules);
+            }
+            commitment.to_string()
+        }
+        _ => return Err(protocol_error(&mut writer, &hello)),
+    };
+
+    // Shots in turn until a fleet is sunk
+    let mut board = board;
+    let mut view = TargetView::for_board(&board);
+    let mut fired: Vec<(Pos, ShotOutcome)> = Vec::new();
+    let mut shown: Vec<(Pos, Vec<Pos>)> = Vec::new();
+    let mut our_turn = first;
+    let mut shots_left = turn_shots(our_turn, &board, &view);
+
+    let won = loop {
+        let outcome = if our_turn {
+            let pos = match shooter.next_shot() {
+                Some(pos) => pos,
+                // with honest answers the fleet is sunk before the cells run out
+                None if view.unknown_cells().is_empty() => {
+                    return Err(NetError::Cheating("every cell has been shot but the fleet is still afloat".to_string()))
+                }
+                None => return Err(NetError::NoShot),
+            };
+            send(&mut writer, &format!("FIRE {},{}", pos.x, pos.y))?;
+
+            let reply = receive(&mut reader)?;
+            let (outcome, cells) = match reply.strip_prefix("RESULT ").and_then(|r| parse_result(r, board.rules().reveal)) {
+                Some(result) => result,
+                None => return Err(protocol_error(&mut writer, &reply)),
+            };
+
+            shooter.record(pos, outcome);
+            view.record(pos, outcome);
+            if let Some(cells) = cells {
+                shooter.reveal(&cells);
+                view.reveal(&cells);
+                shown.push((pos, cells));
+            }
+            fired.push((pos, outcome));
+            events(Event::Fired(pos, outcome));
+
+            if view.afloat().iter().all(|&n| n == 0) {
+                break true;
+            }
+            outcome
+        } else {
+            let message = receive(&mut reader)?;
+            let pos = match message.strip_prefix("FIRE ").and_then(parse_pos) {
+                Some(pos) => pos,
+                None => return Err(protocol_error(&mut writer, &message)),
+            };
+            let outcome = match board.fire(pos) {
+                Ok(outcome) => outcome,
+                Err(e) => return Err(protocol_error(&mut writer, &format!("{} ({})", message, e))),
+            };
+
+            let mut result = format_outcome(outcome);
+            if let (ShotOutcome::Sunk(_), true, Some(id)) = (outcome, board.rules().reveal, board.boat_id(pos)) {
+                result = format!("{} {}", result, format_offsets(&board.boat_cells(id)));
+            }
+            send(&mut writer, &format!("RESULT {}", result))?;
+            events(Event::Received(pos, outcome));
+
+            if board.all_sunk() {
+                break false;
+            }
+            outcome
+        };
+
+        // a shot at a cell already shot doesn't count
+        if outcome == ShotOutcome::AlreadyShot {
+            continue;
+        }
+        shots_left -= 1;
+        if shots_left == 0 {
+            our_turn = !our_turn;
+            shots_left = turn_shots(our_turn, &board, &view);
+        }
+    };
+
+    // Reveal the boards and check the opponent didn't lie
+    send(&mut writer, &format!("REVEAL {} {}", salt, setup.lines().collect::<Vec<_>>().join("|")))?;
+
+    let reveal = receive(&mut reader)?;
+    let (their_salt, their_setup) = match reveal.strip_prefix("REVEAL ").and_then(|r| r.split_once(' ')) {
+        Some((salt, board)) => (salt, board.replace('|', "\n") + "\n"),
+        None => return Err(protocol_error(&mut writer, &reveal)),
+    };
+    if commitment(their_salt, &their_setup) != their_commitment {
+        return Err(NetError::Cheating("the revealed board is not the one committed at the start".to_string()));
+    }
+    verify(&their_setup, &board, &fired, &shown)?;
+
+    Ok(won)
+}
+
+// Shots of a turn: one, or with the salvo rule one for each boat the attacker has afloat
+// (our boats are on our board, the opponent's are the ones we haven't sunk yet)
+fn turn_shots(our_turn: bool, board: &Board, view: &TargetView) -> usize {
+    if !board.rules().salvo {
+        return 1;
+    }
+
+    let afloat = if our_turn { board.boats_afloat() } else { view.afloat().iter().map(|&n| n as usize).sum() };
+    std::cmp::max(afloat, 1)
+}
+
+// The opponent's board must be a legal fleet with our rules and give the outcomes (and sunk boats) they reported
+fn verify(setup: &str, ours: &Board, fired: &[(Pos, ShotOutcome)], shown: &[(Pos, Vec<Pos>)]) -> Result<(), NetError> {
+    let revealed = Board::from(setup.to_string())
+        .map_err(|e| NetError::Cheating(format!("invalid revealed board: {}", e)))?;
+    let mut theirs = rebuild(&revealed)
+        .filter(|b| {
+            b.width() == ours.width()
+                && b.height() == ours.height()
+                && format_fleet(b) == format_fleet(ours)
+                && b.rules() == ours.rules()
+        })
+        .ok_or_else(|| NetError::Cheating("the revealed fleet breaks the rules".to_string()))?;
+
+    for (pos, reported) in fired {
+        let actual = theirs.fire(*pos).map_err(|e| NetError::Cheating(e.to_string()))?;
+        if actual != *reported {
+            return Err(NetError::Cheating(format!(
+                "shot at ({}, {}) was reported as {} but it was {}",
+                pos.x,
+                pos.y,
+                format_outcome(*reported),
+                format_outcome(actual)
+            )));
+        }
+    }
+
+    for (pos, cells) in shown {
+        let actual = theirs.boat_id(*pos).map(|id| theirs.boat_cells(id)).unwrap_or_default();
+        if actual != *cells {
+            return Err(NetError::Cheating(format!("the boat sunk at ({}, {}) was not where they showed it", pos.x, pos.y)));
+        }
+    }
+
+    Ok(())
+}
+
+// Place again every boat of a board on an empty board with the same rules, so that overlaps,
+// touching boats and the quota are checked; None if any boat can't be placed
+fn rebuild(board: &Board) -> Option<Board> {
+    if !board.is_complete() {
+        return None;
+    }
+
+    let pieces: Vec<_> = board.pieces().iter().cloned().zip(board.piece_fleet().iter().copied()).collect();
+    let mut rebuilt = Board::with_pieces(board.width(), board.height(), board.fleet(), &pieces).ok()?;
+    rebuilt.set_rules(board.rules()).ok()?;
+    let mut done: Vec<u8> = Vec::new();
+
+    for x in 0..board.height() {
+        for y in 0..board.width() {
+            let id = match board.boat_id(Pos { x, y }) {
+                Some(id) if !done.contains(&id) => id,
+                _ => continue,
+            };
+            done.push(id);
+
+            // Board::from already checked every boat is a straight line or a piece of the fleet
+            let (boat, pos) = board.placement(id)?;
+            rebuilt.add_boat(boat, pos).ok()?;
+        }
+    }
+
+    Some(rebuilt)
+}
+
+/*
+ * Helper functions:
+ *
+ * send writes a message, receive reads the next one (at most MAX_LINE bytes)
+ * protocol_error tells the opponent about an unexpected message before giving up
+ * commitment hashes salt and board
+ * random_salt gives SALT_BYTES random bytes from the operating system, in hex
+ * join_counts, format_fleet, parse_pos, parse_result, parse_outcome and format_outcome convert to and from the messages
+ */
+
+fn send<W: Write>(writer: &mut W, message: &str) -> Result<(), NetError> {
+    writeln!(writer, "{}", message).map_err(NetError::Io)?;
+    writer.flush().map_err(NetError::Io)
+}
+
+fn receive<R: BufRead>(reader: &mut R) -> Result<String, NetError> {
+    let mut line = String::new();
+    // a line without end must not fill the memory: read one byte more than allowed to notice it
+    if reader.by_ref().take(MAX_LINE as u64 + 1).read_line(&mut line).map_err(NetError::Io)? == 0 {
+        return Err(NetError::Disconnected);
+    }
+    if line.len() > MAX_LINE {
+        return Err(NetError::Protocol(format!("message longer than {} bytes", MAX_LINE)));
+    }
+
+    let line = line.trim_end().to_string();
+    match line.strip_prefix("ERROR ") {
+        Some(message) => Err(NetError::Protocol(format!("the opponent reported: {}", message))),
+        None => Ok(line),
+    }
+}
+
+fn protocol_error<W: Write>(writer: &mut W, message: &str) -> NetError {
+    let _ = send(writer, &format!("ERROR unexpected message: {}", message));
+    NetError::Protocol(format!("unexpected message: {}", message))
+}
+
+fn commitment(salt: &str, board: &str) -> String {
+    let digest = Sha256::digest(format!("{}{}", salt, board).as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn random_salt() -> Result<String, NetError> {
+    let mut bytes = [0u8; SALT_BYTES];
+    getrandom::getrandom(&mut bytes).map_err(|e| NetError::Io(io::Error::other(e.to_string())))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn join_counts(counts: &[u8]) -> String {
+    counts.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",")
+}
+
+// Fleet as sent in HELLO, pieces included
+fn format_fleet(board: &Board) -> String {
+    let mut fleet = join_counts(board.fleet());
+    for (piece, count) in board.pieces().iter().zip(board.piece_fleet().iter()) {
+        fleet.push_str(&format!("+{}:{}:{}", piece.name(), count, format_offsets(&piece.cells(0))));
+    }
+    fleet
+}
+
+fn parse_pos(s: &str) -> Option<Pos> {
+    let (x, y) = s.split_once(',')?;
+    Some(Pos { x: x.parse().ok()?, y: y.parse().ok()? })
+}
+
+fn parse_outcome(s: &str) -> Option<ShotOutcome> {
+    match s {
+        "miss" => Some(ShotOutcome::Miss),
+        "hit" => Some(ShotOutcome::Hit),
+        "already" => Some(ShotOutcome::AlreadyShot),
+        sunk => Some(ShotOutcome::Sunk(sunk.strip_prefix("sunk:")?.parse().ok()?)),
+    }
+}
+
+// Outcome of a RESULT message, with the cells of the sunk boat when the rules reveal them
+fn parse_result(s: &str, reveal: bool) -> Option<(ShotOutcome, Option<Vec<Pos>>)> {
+    match (s.split_once(' '), reveal) {
+        (Some((outcome, cells)), true) => match parse_outcome(outcome)? {
+            ShotOutcome::Sunk(length) => {
+                let cells = parse_offsets(cells).filter(|c| c.len() == length)?;
+                Some((ShotOutcome::Sunk(length), Some(cells)))
+            }
+            _ => None,
+        },
+        (None, true) if matches!(parse_outcome(s)?, ShotOutcome::Sunk(_)) => None,
+        (None, _) => Some((parse_outcome(s)?, None)),
+        (Some(_), false) => None,
+    }
+}
+
+fn format_outcome(outcome: ShotOutcome) -> String {
+    match outcome {
+        ShotOutcome::Miss => "miss".to_string(),
+        ShotOutcome::Hit => "hit".to_string(),
+        ShotOutcome::Sunk(length) => format!("sunk:{}", length),
+        ShotOutcome::AlreadyShot => "already".to_string(),
+    }
+}
+
+impl std::fmt::Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NetError::Io(e) => write!(f, "connection error: {}", e),
+            NetError::Disconnected => write!(f, "the opponent closed the connection"),
+            NetError::Protocol(message) => write!(f, "protocol error: {}", message),
+            NetError::Rules => write!(f, "the boards have different size or fleet"),
+            NetError::Setup => write!(f, "the fleet must be completely placed, and not shot, before playing"),
+            NetError::NoShot => write!(f, "no shot left to fire"),
+            NetError::Cheating(reason) => write!(f, "the opponent cheated: {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use crate::ai::Strategy;
+    use crate::board::Rules;
+
+    fn fleet(rules: Rules, seed: u64) -> Board {
+        let mut board = Board::with_rules(6, 6, &[1, 1, 1]).unwrap();
+        board.set_rules(rules).unwrap();
+        board.random_fleet(seed).unwrap();
+        board
+    }
+
+    // One side on each end of a connection over the loopback interface, the computer shooting for both;
+    // gives what play returned to the side that accepted the connection and to the one that joined
+    fn loopback(first: Board, second: Board, shooter: &mut dyn Ai) -> (Result<bool, NetError>, Result<bool, NetError>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let joiner = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            let mut ai = Strategy::HuntTarget.create(&second, 2);
+            play(reader, stream, second, ai.as_mut(), false, &mut |_| {})
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        let served = play(reader, stream, first, shooter, true, &mut |_| {});
+
+        (served, joiner.join().unwrap())
+    }
+
+    #[test]
+    fn game_over_loopback() {
+        for rules in [Rules::default(), Rules { salvo: true, touching: true, reveal: true }] {
+            let first = fleet(rules, 1);
+            let mut ai = Strategy::Density.create(&first, 1);
+            let (served, joined) = loopback(first, fleet(rules, 2), ai.as_mut());
+
+            let (served, joined) = (served.unwrap(), joined.unwrap());
+            assert_ne!(served, joined, "exactly one side wins with {}", rules);
+        }
+    }
+
+    // Shoots like another Ai, keeping the sunk boats and the cells shown for them
+    struct Recorder {
+        ai: Box<dyn Ai>,
+        sunk: Vec<usize>,
+        shown: Vec<Vec<Pos>>,
+    }
+
+    impl Ai for Recorder {
+        fn next_shot(&mut self) -> Option<Pos> {
+            self.ai.next_shot()
+        }
+
+        fn record(&mut self, pos: Pos, outcome: ShotOutcome) {
+            if let ShotOutcome::Sunk(length) = outcome {
+                self.sunk.push(length);
+            }
+            self.ai.record(pos, outcome);
+        }
+
+        fn reveal(&mut self, boat: &[Pos]) {
+            self.shown.push(boat.to_vec());
+            self.ai.reveal(boat);
+        }
+    }
+
+    #[test]
+    fn sunk_boats_are_shown_with_reveal() {
+        for reveal in [false, true] {
+            let rules = Rules { touching: true, reveal, ..Rules::default() };
+            let first = fleet(rules, 3);
+            let mut recorder = Recorder { ai: Strategy::Density.create(&first, 3), sunk: Vec::new(), shown: Vec::new() };
+            let (served, joined) = loopback(first, fleet(rules, 4), &mut recorder);
+            served.unwrap();
+            joined.unwrap();
+
+            assert!(!recorder.sunk.is_empty());
+            let shown: Vec<usize> = recorder.shown.iter().map(|cells| cells.len()).collect();
+            assert_eq!(shown, if reveal { recorder.sunk } else { Vec::new() });
+        }
+    }
+
+    #[test]
+    fn different_rules_are_refused() {
+        let first = fleet(Rules::default(), 1);
+        let mut ai = Strategy::Random.create(&first, 1);
+        let (served, _) = loopback(first, fleet(Rules { salvo: true, ..Rules::default() }, 2), ai.as_mut());
+
+        assert!(matches!(served, Err(NetError::Rules)));
+    }
+
+    #[test]
+    fn long_lines_are_refused() {
+        let message = format!("FIRE {}\nFIRE 1,1\n", "9".repeat(MAX_LINE));
+        let mut reader = message.as_bytes();
+        assert!(matches!(receive(&mut reader), Err(NetError::Protocol(_))));
+
+        let message = format!("REVEAL {}\n", "a".repeat(MAX_LINE - 8));
+        assert_eq!(receive(&mut message.as_bytes()).unwrap().len(), MAX_LINE - 1);
+    }
+
+    #[test]
+    fn salts_are_long_and_different() {
+        let (a, b) = (random_salt().unwrap(), random_salt().unwrap());
+        assert_eq!(a.len(), 2 * SALT_BYTES);
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,129 @@
+/*
+ * Pieces: boats that are not straight lines (L and T shapes, the 2x2 island, or any other polyomino)
+ * A piece is given by the offsets of its cells from the top left corner of the smallest rectangle
+ * holding it, and can be placed in four rotations (quarter turns clockwise)
+ * Straight shapes are not pieces: they are the usual vertical and horizontal boats
+ */
+
+use crate::board::Pos;
+
+pub const ROTATIONS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    name: char,             // letter used to refer to the piece, e.g. 'L'
+    cells: Vec<Pos>,        // offsets in rotation 0, sorted by row then column
+}
+
+impl Piece {
+
+    // Piece from the offsets of its cells, None if they are not a connected shape or they are a straight line
+    pub fn new(name: char, cells: &[Pos]) -> Option<Piece> {
+        if !name.is_ascii_alphabetic() || cells.is_empty() {
+            return None;
+        }
+
+        let cells = normalize(cells.to_vec());
+        if cells.windows(2).any(|w| w[0] == w[1]) || !connected(&cells) {
+            return None;
+        }
+        let (rows, cols) = size(&cells);
+        if rows == 1 || cols == 1 {
+            return None;
+        }
+
+        Some(Piece { name, cells })
+    }
+
+    // The pieces everybody knows: 'L', 'T' and 'O' (the 2x2 island)
+    pub fn standard(name: char) -> Option<Piece> {
+        let offsets: &[(usize, usize)] = match name {
+            'L' => &[(0, 0), (1, 0), (2, 0), (2, 1)],
+            'T' => &[(0, 0), (0, 1), (0, 2), (1, 1)],
+            'O' => &[(0, 0), (0, 1), (1, 0), (1, 1)],
+            _ => return None,
+        };
+        let cells: Vec<Pos> = offsets.iter().map(|&(x, y)| Pos { x, y }).collect();
+
+        Piece::new(name, &cells)
+    }
+
+    pub fn name(&self) -> char {
+        self.name
+    }
+
+    // Number of cells
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    // Offsets of the cells after the given number of quarter turns clockwise, sorted by row then column
+    pub fn cells(&self, rotation: usize) -> Vec<Pos> {
+        let mut cells = self.cells.clone();
+        for _ in 0..rotation % ROTATIONS {
+            let (rows, _) = size(&cells);
+            cells = normalize(cells.iter().map(|p| Pos { x: p.y, y: rows - 1 - p.x }).collect());
+        }
+        cells
+    }
+
+    // Rows and columns of the smallest rectangle holding the piece in the given rotation
+    pub fn size(&self, rotation: usize) -> (usize, usize) {
+        size(&self.cells(rotation))
+    }
+
+    // Rotation in which the piece covers exactly the given cells (as offsets from their top left corner)
+    pub fn rotation_of(&self, cells: &[Pos]) -> Option<usize> {
+        let cells = normalize(cells.to_vec());
+        (0..ROTATIONS).find(|&r| self.cells(r) == cells)
+    }
+
+    // Same shape, possibly rotated
+    pub fn same_shape(&self, other: &Piece) -> bool {
+        other.rotation_of(&self.cells).is_some()
+    }
+}
+
+/*
+ * Helper functions:
+ *
+ * normalize moves the cells so that their top left corner is (0, 0) and sorts them
+ * size gives rows and columns of the rectangle holding the cells
+ * connected checks that every cell can be reached from the first one going through sides
+ */
+
+fn normalize(cells: Vec<Pos>) -> Vec<Pos> {
+    let top = cells.iter().map(|p| p.x).min().unwrap_or(0);
+    let left = cells.iter().map(|p| p.y).min().unwrap_or(0);
+
+    let mut cells: Vec<Pos> = cells.iter().map(|p| Pos { x: p.x - top, y: p.y - left }).collect();
+    cells.sort_by_key(|p| (p.x, p.y));
+    cells
+}
+
+fn size(cells: &[Pos]) -> (usize, usize) {
+    let rows = cells.iter().map(|p| p.x + 1).max().unwrap_or(0);
+    let cols = cells.iter().map(|p| p.y + 1).max().unwrap_or(0);
+    (rows, cols)
+}
+
+fn connected(cells: &[Pos]) -> bool {
+    let mut reached = vec![cells[0]];
+    let mut i = 0;
+
+    while i < reached.len() {
+        let p = reached[i];
+        for n in cells.iter() {
+            if p.x.abs_diff(n.x) + p.y.abs_diff(n.y) == 1 && !reached.contains(n) {
+                reached.push(*n);
+            }
+        }
+        i += 1;
+    }
+
+    reached.len() == cells.len()
+}
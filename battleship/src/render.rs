@@ -0,0 +1,238 @@
+/*
+ * Terminal rendering of the boards
+ * Rows are labelled with letters (A, B, C, ...) and columns with numbers (1, 2, 3, ...), as typed in the
+ * commands: the cell in row C under column 7 is C7
+ * A grid is a list of lines so that two grids can be drawn side by side
+ *
+ *   ~  water       #  boat        X  hit boat
+ *   o  miss        @  sunk boat   .  not shot yet (opponent's grid)
+ *
+ * A heatmap shows a probability in each cell as a digit, from 0 (below 10%) to 9 (90% or more)
+ */
+
+use crate::board::{Board, Pos};
+use crate::target::{Mark, TargetView};
+
+const CELL_WIDTH: usize = 3;
+const LABEL_WIDTH: usize = 2;
+const GAP: usize = 6;           // spaces between two grids drawn side by side
+
+// ANSI escape sequences
+const RESET: &str = "\x1b[0m";
+const BLUE: &str = "\x1b[34m";
+const WHITE: &str = "\x1b[1;37m";
+const RED: &str = "\x1b[1;31m";
+const GREY: &str = "\x1b[90m";
+const MAGENTA: &str = "\x1b[35m";
+const HEAT: [&str; 10] = [        // background colours of the heatmap digits, from cold to hot
+    "\x1b[48;5;17m", "\x1b[48;5;18m", "\x1b[48;5;19m", "\x1b[48;5;54m", "\x1b[48;5;90m",
+    "\x1b[48;5;126m", "\x1b[48;5;161m", "\x1b[48;5;196m", "\x1b[48;5;202m", "\x1b[48;5;208m",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Glyph {
+    Water,
+    Boat,
+    Hit,
+    Miss,
+    Sunk,
+    Unknown,
+}
+
+impl Glyph {
+
+    fn symbol(&self) -> char {
+        match self {
+            Glyph::Water => '~',
+            Glyph::Boat => '#',
+            Glyph::Hit => 'X',
+            Glyph::Miss => 'o',
+            Glyph::Sunk => '@',
+            Glyph::Unknown => '.',
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Glyph::Water => BLUE,
+            Glyph::Boat => WHITE,
+            Glyph::Hit => RED,
+            Glyph::Miss => GREY,
+            Glyph::Sunk => MAGENTA,
+            Glyph::Unknown => RESET,
+        }
+    }
+}
+
+// Own grid: where the boats are and where the opponent has shot
+pub fn own_grid(board: &Board, color: bool) -> Vec<String> {
+    grid(board.width(), board.height(), |pos| {
+        let glyph = match (board.boat_id(pos), board.is_shot(pos)) {
+            (None, false) => Glyph::Water,
+            (None, true) => Glyph::Miss,
+            (Some(_), false) => Glyph::Boat,
+            (Some(id), true) if board.is_sunk(id) => Glyph::Sunk,
+            (Some(_), true) => Glyph::Hit,
+        };
+        paint(glyph, color)
+    })
+}
+
+// Opponent's grid: only what has been learned by shooting
+pub fn target_grid(view: &TargetView, color: bool) -> Vec<String> {
+    grid(view.width(), view.height(), |pos| {
+        let glyph = match view.get(pos) {
+            Mark::Unknown => Glyph::Unknown,
+            Mark::Miss => Glyph::Miss,
+            Mark::Hit => Glyph::Hit,
+            Mark::Sunk => Glyph::Sunk,
+            Mark::Water => Glyph::Water,
+        };
+        paint(glyph, color)
+    })
+}
+
+// Probabilities (probabilities[x][y] between 0 and 1) as digits, hotter colours for higher values
+pub fn heatmap(probabilities: &[Vec<f64>], color: bool) -> Vec<String> {
+    let width = probabilities.first().map_or(0, |row| row.len());
+
+    grid(width, probabilities.len(), |pos| {
+        let level = std::cmp::min((probabilities[pos.x][pos.y] * 10.0) as usize, 9);
+        if color {
+            format!("{}{}{}", HEAT[level], level, RESET)
+        } else {
+            level.to_string()
+        }
+    })
+}
+
+// Two grids next to each other, each with a title above it
+pub fn side_by_side(left_title: &str, left: &[String], right_title: &str, right: &[String]) -> String {
+    // the lines may contain colours: pad on the visible width, not on the length of the string
+    let left_width = left.first().map_or(0, |l| visible_width(l));
+    let mut s = format!("{:width$}{}\n", left_title, right_title, width = left_width + GAP);
+
+    for i in 0..std::cmp::max(left.len(), right.len()) {
+        let l = left.get(i).map_or("", |l| l.as_str());
+        let r = right.get(i).map_or("", |r| r.as_str());
+        s += &format!("{}{}{}\n", l, " ".repeat(left_width - visible_width(l) + GAP), r);
+    }
+
+    s
+}
+
+// Letter of a row, as written on the grid labels
+pub fn row_label(x: usize) -> char {
+    (b'A' + x as u8) as char
+}
+
+// Name of a cell as written on the grid labels, e.g. Pos { x: 2, y: 6 } -> "C7"
+pub fn cell_label(pos: Pos) -> String {
+    format!("{}{}", row_label(pos.x), pos.y + 1)
+}
+
+// Legend of the symbols
+pub fn legend(color: bool) -> String {
+    let items = [
+        (Glyph::Water, "water"),
+        (Glyph::Boat, "boat"),
+        (Glyph::Hit, "hit"),
+        (Glyph::Miss, "miss"),
+        (Glyph::Sunk, "sunk"),
+        (Glyph::Unknown, "not shot"),
+    ];
+
+    items
+        .iter()
+        .map(|(glyph, name)| format!("{} {}", paint(*glyph, color), name))
+        .collect::<Vec<_>>()
+        .join("   ")
+}
+
+/*
+ * Helper functions:
+ *
+ * grid draws the header with the column numbers and a line for each row, cell writes the content of a cell
+ * paint writes a glyph, with its colour if needed
+ * visible_width counts the characters of a line without the colour escape sequences
+ */
+
+fn grid(width: usize, height: usize, cell: impl Fn(Pos) -> String) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    let mut header = " ".repeat(LABEL_WIDTH);
+    for y in 0..width {
+        header += &format!("{:>width$}", y + 1, width = CELL_WIDTH);
+    }
+    lines.push(header);
+
+    for x in 0..height {
+        let mut line = format!("{:<width$}", row_label(x), width = LABEL_WIDTH);
+        for y in 0..width {
+            line += &" ".repeat(CELL_WIDTH - 1);
+            line += &cell(Pos { x, y });
+        }
+        lines.push(line);
+    }
+
+    lines
+}
+
+fn paint(glyph: Glyph, color: bool) -> String {
+    if color {
+        format!("{}{}{}", glyph.color(), glyph.symbol(), RESET)
+    } else {
+        glyph.symbol().to_string()
+    }
+}
+
+fn visible_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut escape = false;
+
+    for c in line.chars() {
+        match (escape, c) {
+            (false, '\x1b') => escape = true,
+            (true, 'm') => escape = false,
+            (true, _) => {}
+            (false, _) => width += 1,
+        }
+    }
+
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Boat;
+
+    #[test]
+    fn rows_are_letters_and_columns_numbers_from_one() {
+        let mut board = Board::with_rules(20, 20, &[0, 1]).unwrap();
+        board.add_boat(Boat::Horizontal(2), Pos { x: 2, y: 6 }).unwrap();
+        board.fire(Pos { x: 19, y: 19 }).unwrap();
+
+        let lines = own_grid(&board, false);
+        assert_eq!(lines.len(), 21);
+        assert!(lines[0].starts_with("    1  2  3"));
+        assert!(lines[0].ends_with(" 19 20"));
+        assert!(lines[1].starts_with("A   ~"));
+        // C7 and C8 hold the boat
+        assert_eq!(lines[3], format!("C {}", "  ~".repeat(6) + "  #  #" + &"  ~".repeat(12)));
+        assert!(lines[20].starts_with("T ") && lines[20].ends_with("~  o"));
+        assert_eq!(cell_label(Pos { x: 2, y: 6 }), "C7");
+        assert_eq!(cell_label(Pos { x: 19, y: 19 }), "T20");
+    }
+
+    #[test]
+    fn side_by_side_aligns_coloured_grids() {
+        let board = Board::with_rules(3, 2, &[1]).unwrap();
+        let view = TargetView::new(3, 2, &[1]);
+        let s = side_by_side("mine", &own_grid(&board, true), "theirs", &target_grid(&view, false));
+
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines[0], format!("{:17}theirs", "mine"));
+        assert!(lines[2].ends_with(&format!("{}A   .  .  .", " ".repeat(GAP))));
+    }
+}
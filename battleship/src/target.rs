@@ -0,0 +1,288 @@
+/*
+ * What a shooter knows about the opponent's board
+ * The boats are hidden: the view only holds the outcome of the shots fired so far
+ * Boats never touch, so the cells around a sunk boat are known to be water
+ * (unless the rules let them touch: then a sunk boat can't be told apart from the hits next to it,
+ * only the cell of the last shot is known to be part of it, and the rest only if the rules reveal it)
+ */
+
+use crate::board::{Board, Pos, Rules, ShotOutcome};
+use crate::piece::ROTATIONS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mark {
+    Unknown,    // Not shot yet
+    Miss,       // Shot, water
+    Hit,        // Shot, part of a boat still afloat
+    Sunk,       // Shot, part of a sunk boat
+    Water,      // Not shot, but it can't hold a boat (next to a sunk boat)
+}
+
+#[derive(Clone)]
+pub struct TargetView {
+    width: usize,
+    height: usize,
+    marks: Vec<Vec<Mark>>,
+    afloat: Vec<u8>,        // afloat[i] = boats of length i + 1 not sunk yet
+    shapes: Vec<Vec<Vec<Pos>>>,     // shapes[i] = cells of the boats of i + 1 cells, in every direction or rotation
+    rules: Rules,
+}
+
+impl TargetView {
+
+    pub fn new(width: usize, height: usize, fleet: &[u8]) -> TargetView {
+        TargetView {
+            width,
+            height,
+            marks: vec![vec![Mark::Unknown; width]; height],
+            afloat: fleet.to_vec(),
+            shapes: (1..=fleet.len()).map(straight_shapes).collect(),
+            rules: Rules::default(),
+        }
+    }
+
+    // Empty view of a board with its size, fleet (pieces included) and rules
+    pub fn for_board(board: &Board) -> TargetView {
+        let mut view = TargetView::new(board.width(), board.height(), &board.sizes());
+        view.rules = board.rules();
+
+        // a straight boat of a length only if the fleet has one, and the pieces in all their rotations
+        for (i, shapes) in view.shapes.iter_mut().enumerate() {
+            if board.fleet().get(i).is_none_or(|&count| count == 0) {
+                shapes.clear();
+            }
+        }
+        for (piece, _) in board.pieces().iter().zip(board.piece_fleet()).filter(|(_, &count)| count > 0) {
+            for rotation in 0..ROTATIONS {
+                let cells = piece.cells(rotation);
+                if !view.shapes[piece.len() - 1].contains(&cells) {
+                    view.shapes[piece.len() - 1].push(cells);
+                }
+            }
+        }
+
+        view
+    }
+
+    // What the opponent knows of a board from the shots it has received, without seeing the boats
+    pub fn of_board(board: &Board) -> TargetView {
+        let mut view = TargetView::for_board(board);
+        let shot: Vec<Pos> = view.cells().filter(|p| board.is_shot(*p)).collect();
+
+        // hits first, so that a sunk boat finds all its cells already hit
+        for pos in shot.iter() {
+            let outcome = if board.boat_id(*pos).is_some() { ShotOutcome::Hit } else { ShotOutcome::Miss };
+            view.record(*pos, outcome);
+        }
+        let mut sunk: Vec<u8> = Vec::new();
+        for pos in shot.iter() {
+            if let Some(id) = board.boat_id(*pos).filter(|&id| board.is_sunk(id) && !sunk.contains(&id)) {
+                sunk.push(id);
+                view.record(*pos, ShotOutcome::Sunk(board.boat_cells(id).len()));
+                if view.rules.reveal {
+                    view.reveal(&board.boat_cells(id));
+                }
+            }
+        }
+
+        view
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, pos: Pos) -> Mark {
+        self.marks[pos.x][pos.y]
+    }
+
+    // Boats not sunk yet: element i is the number of boats of length i + 1
+    pub fn afloat(&self) -> &[u8] {
+        &self.afloat
+    }
+
+    // Offsets of the cells of the boats with the given number of cells, from the top left corner
+    // of their rectangle, in every direction or rotation they can be placed
+    pub fn shapes(&self, size: usize) -> &[Vec<Pos>] {
+        size.checked_sub(1).and_then(|i| self.shapes.get(i)).map_or(&[], |shapes| shapes.as_slice())
+    }
+
+    // Update the view with the outcome of a shot at pos
+    pub fn record(&mut self, pos: Pos, outcome: ShotOutcome) {
+        match outcome {
+            ShotOutcome::Miss => self.marks[pos.x][pos.y] = Mark::Miss,
+            ShotOutcome::Hit => self.marks[pos.x][pos.y] = Mark::Hit,
+            ShotOutcome::Sunk(length) => {
+                self.marks[pos.x][pos.y] = Mark::Hit;
+
+                // the boat is made of the hits connected to the last shot, when boats can't touch
+                if self.rules.touching {
+                    self.marks[pos.x][pos.y] = Mark::Sunk;
+                } else {
+                    let boat = self.hit_group(pos);
+                    self.reveal(&boat);
+                }
+
+                if let Some(count) = self.afloat.get_mut(length.wrapping_sub(1)) {
+                    *count = count.saturating_sub(1);
+                }
+            }
+            ShotOutcome::AlreadyShot => {}
+        }
+    }
+
+    // The cells of a sunk boat are known: they are marked sunk and, if boats can't touch, the cells around them water
+    pub fn reveal(&mut self, boat: &[Pos]) {
+        for p in boat.iter() {
+            self.marks[p.x][p.y] = Mark::Sunk;
+        }
+        if self.rules.touching {
+            return;
+        }
+        for p in boat.iter() {
+            for n in self.around(*p) {
+                if self.marks[n.x][n.y] == Mark::Unknown {
+                    self.marks[n.x][n.y] = Mark::Water;
+                }
+            }
+        }
+    }
+
+    // Cells that may still be worth a shot
+    pub fn unknown_cells(&self) -> Vec<Pos> {
+        self.cells().filter(|p| self.get(*p) == Mark::Unknown).collect()
+    }
+
+    // Cells of boats hit but still afloat
+    pub fn open_hits(&self) -> Vec<Pos> {
+        self.cells().filter(|p| self.get(*p) == Mark::Hit).collect()
+    }
+
+    // Hits connected to pos horizontally or vertically: the cells found so far of the boat in pos
+    pub fn hit_group(&self, pos: Pos) -> Vec<Pos> {
+        let mut group = vec![pos];
+        let mut i = 0;
+
+        while i < group.len() {
+            for n in self.sides(group[i]) {
+                if self.get(n) == Mark::Hit && !group.contains(&n) {
+                    group.push(n);
+                }
+            }
+            i += 1;
+        }
+
+        group
+    }
+
+    // The up to 4 cells sharing a side with pos
+    pub fn sides(&self, pos: Pos) -> Vec<Pos> {
+        let mut sides = Vec::new();
+        if pos.x > 0 {
+            sides.push(Pos { x: pos.x - 1, y: pos.y });
+        }
+        if pos.x + 1 < self.height {
+            sides.push(Pos { x: pos.x + 1, y: pos.y });
+        }
+        if pos.y > 0 {
+            sides.push(Pos { x: pos.x, y: pos.y - 1 });
+        }
+        if pos.y + 1 < self.width {
+            sides.push(Pos { x: pos.x, y: pos.y + 1 });
+        }
+        sides
+    }
+
+    // The up to 8 cells touching pos, diagonals included
+    pub fn around(&self, pos: Pos) -> Vec<Pos> {
+        let mut around = Vec::new();
+        for x in pos.x.saturating_sub(1)..std::cmp::min(pos.x + 2, self.height) {
+            for y in pos.y.saturating_sub(1)..std::cmp::min(pos.y + 2, self.width) {
+                if (x, y) != (pos.x, pos.y) {
+                    around.push(Pos { x, y });
+                }
+            }
+        }
+        around
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = Pos> {
+        let width = self.width;
+        (0..self.height).flat_map(move |x| (0..width).map(move |y| Pos { x, y }))
+    }
+}
+
+// A horizontal and a vertical line of the given length (only one for a single cell)
+fn straight_shapes(length: usize) -> Vec<Vec<Pos>> {
+    let horizontal: Vec<Pos> = (0..length).map(|y| Pos { x: 0, y }).collect();
+    let vertical: Vec<Pos> = (0..length).map(|x| Pos { x, y: 0 }).collect();
+
+    if length == 1 {
+        vec![horizontal]
+    } else {
+        vec![horizontal, vertical]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Boat;
+
+    // Two boats of length 2 side by side, the first one sunk by a shot in (1, 1), as a game would record it
+    fn sunk_next_to_another(rules: Rules) -> TargetView {
+        let mut board = Board::with_rules(4, 4, &[0, 2]).unwrap();
+        board.set_rules(rules).unwrap();
+        board.add_boat(Boat::Horizontal(2), Pos { x: 1, y: 0 }).unwrap();
+        board.add_boat(Boat::Horizontal(2), Pos { x: 2, y: 0 }).unwrap();
+
+        let mut view = TargetView::for_board(&board);
+        for pos in [Pos { x: 2, y: 0 }, Pos { x: 1, y: 0 }, Pos { x: 1, y: 1 }] {
+            let outcome = board.fire(pos).unwrap();
+            view.record(pos, outcome);
+            if let (ShotOutcome::Sunk(_), true, Some(id)) = (outcome, rules.reveal, board.boat_id(pos)) {
+                view.reveal(&board.boat_cells(id));
+            }
+        }
+        view
+    }
+
+    #[test]
+    fn sunk_boat_without_reveal() {
+        let view = sunk_next_to_another(Rules { touching: true, ..Rules::default() });
+
+        // only the last shot is known to be part of the sunk boat
+        assert_eq!(view.afloat(), &[0, 1]);
+        assert_eq!(view.get(Pos { x: 1, y: 1 }), Mark::Sunk);
+        assert_eq!(view.get(Pos { x: 1, y: 0 }), Mark::Hit);
+        assert_eq!(view.get(Pos { x: 2, y: 0 }), Mark::Hit);
+    }
+
+    #[test]
+    fn sunk_boat_with_reveal() {
+        let view = sunk_next_to_another(Rules { touching: true, reveal: true, ..Rules::default() });
+
+        assert_eq!(view.get(Pos { x: 1, y: 0 }), Mark::Sunk);
+        assert_eq!(view.get(Pos { x: 1, y: 1 }), Mark::Sunk);
+        assert_eq!(view.open_hits(), vec![Pos { x: 2, y: 0 }]);
+        // boats may touch: the cells around are still unknown
+        assert_eq!(view.get(Pos { x: 0, y: 0 }), Mark::Unknown);
+    }
+
+    #[test]
+    fn sunk_boat_apart_is_surrounded_by_water() {
+        let mut board = Board::with_rules(4, 4, &[0, 1]).unwrap();
+        board.add_boat(Boat::Vertical(2), Pos { x: 1, y: 1 }).unwrap();
+        board.fire(Pos { x: 1, y: 1 }).unwrap();
+        board.fire(Pos { x: 2, y: 1 }).unwrap();
+        let view = TargetView::of_board(&board);
+
+        assert_eq!(view.get(Pos { x: 1, y: 1 }), Mark::Sunk);
+        assert_eq!(view.get(Pos { x: 3, y: 2 }), Mark::Water);
+        assert_eq!(view.get(Pos { x: 3, y: 3 }), Mark::Unknown);
+    }
+}
@@ -0,0 +1,315 @@
+/*
+ * Validation of boards coming from other tools or edited by hand
+ * Only the occupied cells matter (boat ids are ignored): they are split into groups of cells touching
+ * on a side, and since boats never touch every group should be a single straight boat or a piece of the fleet
+ * A group that is neither is split into straight boats, which are then reported as touching each other
+ * Every problem found is listed in the report with its coordinates
+ * When the rules of a board let boats touch, its boats are told apart by their ids instead
+ *
+ * Besides the board file format, plain grids are accepted: one line per row, '0' or '.' for water
+ * and '1' or '#' for a boat
+ */
+
+use crate::board::{parse_header, parse_piece, straight, Board, Boat, ParseError, Pos, MAX_BOATS};
+use crate::piece::Piece;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    NotStraight(Vec<Pos>),                                      // Cells of a boat that are not a straight line nor a piece
+    Touching(Pos, Pos),                                         // Cells of two boats touching on a side or diagonally
+    TooLong(Pos, usize),                                        // Boat (first cell and length) longer than any boat of the fleet
+    Quota { length: usize, expected: usize, found: usize },     // Wrong number of boats of a length
+    PieceQuota { name: char, expected: usize, found: usize },   // Wrong number of pieces of a shape
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub boats: Vec<(Boat, Pos)>,        // boats and pieces found, with their top left corner
+    pub violations: Vec<Violation>,
+}
+
+// Grid read from a file: which cells are occupied and, if the file has a header, the boats and pieces declared as placed
+pub struct Grid {
+    pub occupied: Vec<Vec<bool>>,
+    pub placed: Option<Vec<u8>>,
+    pub pieces: Vec<(Piece, u8)>,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl Board {
+
+    // Check the boats on the board against the rules and the boats declared as placed
+    pub fn validate(&self) -> Report {
+        let occupied: Vec<Vec<bool>> = (0..self.height())
+            .map(|x| (0..self.width()).map(|y| self.boat_id(Pos { x, y }).is_some()).collect())
+            .collect();
+        let placed: Vec<u8> = self.fleet().iter().zip(self.remaining_boats()).map(|(f, r)| f.saturating_sub(*r)).collect();
+        let pieces: Vec<(Piece, u8)> = self
+            .pieces()
+            .iter()
+            .zip(self.piece_fleet().iter().zip(self.remaining_pieces()))
+            .map(|(piece, (f, r))| (piece.clone(), f.saturating_sub(*r)))
+            .collect();
+
+        if !self.rules().touching {
+            return validate(&occupied, &placed, &pieces);
+        }
+        let groups: Vec<Vec<Pos>> = (1..=MAX_BOATS).map(|id| self.boat_cells(id)).filter(|cells| !cells.is_empty()).collect();
+        check(&groups, &occupied, &placed, &pieces, false)
+    }
+}
+
+// Split the occupied cells into boats and compare them with the expected fleet (expected[i] = boats of length i + 1)
+// and the expected pieces (each with the number of them)
+pub fn validate(occupied: &[Vec<bool>], expected: &[u8], pieces: &[(Piece, u8)]) -> Report {
+    check(&groups(occupied), occupied, expected, pieces, true)
+}
+
+// Check that each group of cells is a boat of the fleet, and if boats can't touch that they don't
+// (groups are then made of cells touching on a side, and the ones that are not a boat are split into straight boats)
+fn check(groups: &[Vec<Pos>], occupied: &[Vec<bool>], expected: &[u8], pieces: &[(Piece, u8)], apart: bool) -> Report {
+    let mut report = Report { boats: Vec::new(), violations: Vec::new() };
+
+    // every group must be a straight line or a piece
+    let mut found = vec![0; expected.len()];
+    let mut found_pieces = vec![0; pieces.len()];
+    let mut boats: Vec<Vec<Pos>> = Vec::new();
+    for cells in groups.iter() {
+        let first = cells[0];
+
+        if straight(cells).is_none() {
+            let piece = pieces.iter().enumerate().find_map(|(i, (piece, _))| piece.rotation_of(cells).map(|r| (i, r)));
+            match piece {
+                Some((i, rotation)) => {
+                    found_pieces[i] += 1;
+                    let corner = Pos { x: first.x, y: cells.iter().map(|p| p.y).min().unwrap_or(first.y) };
+                    report.boats.push((Boat::Piece(pieces[i].0.name(), rotation), corner));
+                    boats.push(cells.clone());
+                    continue;
+                }
+                None if !apart => {
+                    report.violations.push(Violation::NotStraight(cells.clone()));
+                    continue;
+                }
+                None => {}
+            }
+        }
+
+        for cells in split(cells) {
+            let boat = straight(&cells).unwrap_or(Boat::Horizontal(cells.len()));
+            match found.get_mut(cells.len() - 1) {
+                Some(count) => *count += 1,
+                None => report.violations.push(Violation::TooLong(cells[0], cells.len())),
+            }
+            report.boats.push((boat, cells[0]));
+            boats.push(cells);
+        }
+    }
+
+    // cells of different boats touching, reported once for each pair of boats
+    if apart {
+        let mut owner: Vec<Vec<Option<usize>>> = occupied.iter().map(|row| vec![None; row.len()]).collect();
+        for (i, cells) in boats.iter().enumerate() {
+            for p in cells.iter() {
+                owner[p.x][p.y] = Some(i);
+            }
+        }
+
+        // the cells after p, on a side first and then diagonally (the ones before p are checked from them)
+        let mut reported: Vec<(usize, usize)> = Vec::new();
+        for directions in [[(0, 1), (1, 0)], [(1, -1), (1, 1)]] {
+            for (i, cells) in boats.iter().enumerate() {
+                for p in cells.iter() {
+                    for (dx, dy) in directions {
+                        let (nx, ny) = (p.x + dx, p.y as isize + dy);
+                        if nx >= occupied.len() || ny < 0 || ny as usize >= occupied[nx].len() {
+                            continue;
+                        }
+                        let n = Pos { x: nx, y: ny as usize };
+                        match owner[n.x][n.y] {
+                            Some(j) if j != i && !reported.contains(&(i.min(j), i.max(j))) => {
+                                reported.push((i.min(j), i.max(j)));
+                                report.violations.push(Violation::Touching(*p, n));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // and the lengths must match the fleet
+    for (i, (&expected, &found)) in expected.iter().zip(found.iter()).enumerate() {
+        if expected as usize != found {
+            report.violations.push(Violation::Quota { length: i + 1, expected: expected as usize, found });
+        }
+    }
+    for ((piece, expected), &found) in pieces.iter().zip(found_pieces.iter()) {
+        if *expected as usize != found {
+            report.violations.push(Violation::PieceQuota { name: piece.name(), expected: *expected as usize, found });
+        }
+    }
+
+    report
+}
+
+// Read a board file or a plain grid, without any check on the boats
+pub fn parse_grid(s: &str) -> Result<Grid, ParseError> {
+    let mut lines: Vec<&str> = s.lines().collect();
+    let mut placed = None;
+    let mut pieces = Vec::new();
+    let mut size = None;
+
+    if lines.first().is_some_and(|l| l.starts_with("battleship")) {
+        let header = parse_header(lines[0])?;
+        placed = Some(header.fleet.iter().zip(header.remaining.iter()).map(|(f, r)| f.saturating_sub(*r)).collect());
+        size = Some((header.width, header.height));
+        for line in lines.iter().skip(1).take(header.pieces) {
+            let (piece, count, remaining) = parse_piece(line)?;
+            pieces.push((piece, count.saturating_sub(remaining)));
+        }
+        lines.drain(..1 + header.pieces.min(lines.len() - 1));
+    }
+
+    let width = size.map_or(lines.first().map_or(0, |l| l.chars().count()), |(w, _)| w);
+    let height = size.map_or(lines.len(), |(_, h)| h);
+    if lines.len() != height || width == 0 {
+        return Err(ParseError::Dimensions);
+    }
+
+    let mut occupied = Vec::new();
+    for (x, line) in lines.iter().enumerate() {
+        if line.chars().count() != width {
+            return Err(ParseError::Dimensions);
+        }
+        let row = line
+            .chars()
+            .enumerate()
+            .map(|(y, c)| match c {
+                '0' | '.' | '*' => Ok(false),
+                '1' | '#' | 'A'..='Z' | 'a'..='z' => Ok(true),
+                _ => Err(ParseError::BadChar(Pos { x, y }, c)),
+            })
+            .collect::<Result<Vec<bool>, ParseError>>()?;
+        occupied.push(row);
+    }
+
+    Ok(Grid { occupied, placed, pieces })
+}
+
+// Groups of occupied cells touching on a side, each sorted by row then column
+fn groups(occupied: &[Vec<bool>]) -> Vec<Vec<Pos>> {
+    let mut seen: Vec<Vec<bool>> = occupied.iter().map(|row| vec![false; row.len()]).collect();
+    let mut groups = Vec::new();
+
+    for x in 0..occupied.len() {
+        for y in 0..occupied[x].len() {
+            if !occupied[x][y] || seen[x][y] {
+                continue;
+            }
+
+            let mut group = vec![Pos { x, y }];
+            seen[x][y] = true;
+            let mut i = 0;
+            while i < group.len() {
+                let p = group[i];
+                let sides = [
+                    (p.x.wrapping_sub(1), p.y),
+                    (p.x + 1, p.y),
+                    (p.x, p.y.wrapping_sub(1)),
+                    (p.x, p.y + 1),
+                ];
+                for (nx, ny) in sides {
+                    if nx < occupied.len() && ny < occupied[nx].len() && occupied[nx][ny] && !seen[nx][ny] {
+                        seen[nx][ny] = true;
+                        group.push(Pos { x: nx, y: ny });
+                    }
+                }
+                i += 1;
+            }
+
+            group.sort_by_key(|p| (p.x, p.y));
+            groups.push(group);
+        }
+    }
+
+    groups
+}
+
+// Split cells touching on a side (sorted by row then column) into straight boats: the first cell left
+// starts a boat going right or down, whichever way has more cells left in a row, until none is left
+fn split(cells: &[Pos]) -> Vec<Vec<Pos>> {
+    let mut left: Vec<Pos> = cells.to_vec();
+    let mut boats = Vec::new();
+
+    while let Some(&first) = left.first() {
+        let run = |step: &dyn Fn(usize) -> Pos| {
+            (0..).map(step).take_while(|p| left.contains(p)).collect::<Vec<Pos>>()
+        };
+        let right = run(&|i| Pos { x: first.x, y: first.y + i });
+        let down = run(&|i| Pos { x: first.x + i, y: first.y });
+
+        let boat = if down.len() > right.len() { down } else { right };
+        left.retain(|p| !boat.contains(p));
+        boats.push(boat);
+    }
+
+    boats
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Violation::NotStraight(cells) => {
+                let cells: Vec<String> = cells.iter().map(|p| format!("({}, {})", p.x, p.y)).collect();
+                write!(f, "cells {} are not a straight boat nor a piece", cells.join(" "))
+            }
+            Violation::Touching(a, b) => write!(f, "boats touch at ({}, {}) and ({}, {})", a.x, a.y, b.x, b.y),
+            Violation::TooLong(p, length) => write!(f, "boat at ({}, {}) is too long: {}", p.x, p.y, length),
+            Violation::Quota { length, expected, found } => {
+                write!(f, "{} boats of length {} expected, {} found", expected, length, found)
+            }
+            Violation::PieceQuota { name, expected, found } => {
+                write!(f, "{} pieces {} expected, {} found", expected, name, found)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_grid(grid: &str, expected: &[u8]) -> Report {
+        validate(&parse_grid(grid).unwrap().occupied, expected, &[])
+    }
+
+    #[test]
+    fn apart_boats_are_valid() {
+        let report = check_grid("1.1\n...\n11.\n", &[2, 1]);
+        assert!(report.is_valid(), "{:?}", report.violations);
+        assert_eq!(report.boats.len(), 3);
+    }
+
+    #[test]
+    fn boats_touching_on_a_side_are_split() {
+        let report = check_grid("111\n111\n...\n", &[0, 0, 2]);
+        assert_eq!(report.boats, vec![(Boat::Horizontal(3), Pos { x: 0, y: 0 }), (Boat::Horizontal(3), Pos { x: 1, y: 0 })]);
+        assert_eq!(report.violations, vec![Violation::Touching(Pos { x: 0, y: 0 }, Pos { x: 1, y: 0 })]);
+
+        let report = check_grid("111\n.1.\n", &[1, 0, 1]);
+        assert_eq!(report.violations, vec![Violation::Touching(Pos { x: 0, y: 1 }, Pos { x: 1, y: 1 })]);
+    }
+
+    #[test]
+    fn boats_touching_diagonally() {
+        let report = check_grid("1.\n.1\n", &[2]);
+        assert_eq!(report.violations, vec![Violation::Touching(Pos { x: 0, y: 0 }, Pos { x: 1, y: 1 })]);
+    }
+}